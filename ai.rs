@@ -3,25 +3,377 @@ extern crate time;
 
 use std::num::Float;
 
-use game::{Action, Board, Summary, Cord};
-use rand::{Rng, task_rng};
-use std::iter::FromIterator;
+use game::{Action, Board, Summary, Cord, Up, Down, Left, Right, exponent};
+use spawn::SpawnPolicy;
+use record::GameRecord;
+use pool::WorkerPool;
+use stats::BatchStats;
+use rand::{Rng, XorShiftRng, SeedableRng, task_rng};
 use std::iter::AdditiveIterator;
+use std::sync::Arc;
+use std::mem;
+use std::cmp::{Less, Greater, Equal};
+use std::io::{BufferedReader, File, IoResult, IoError, InvalidInput};
 
+//A player sees one game at a time: new_game, then next_action and observe
+//for every move, then end_game. It can keep whatever state it likes between
+//them, such as a search tree or caches.
 pub trait AIPlayer {
-  fn next_action(&self, board : &Board) -> Action;
+  fn new_game(&mut self) {
+  }
+
+  fn next_action(&mut self, board : &Board) -> Action;
+
+  //Same as next_action, plus how much work went into it for players that search
+  fn search(&mut self, board : &Board) -> (Action, SearchStats) {
+    (self.next_action(board), SearchStats::new())
+  }
+
+  //The move played, where the tile spawned after it and the resulting board
+  fn observe(&mut self, _action : Action, _spawn : (uint, int), _board : &Board) {
+  }
+
+  fn end_game(&mut self, _report : &Report) {
+  }
+
+  //Called on a fresh copy of the player before game number game of a batch
+  //played from seed, so its random choices do not depend on the task that
+  //plays it. Players that draw random numbers take them from seeded_rng.
+  fn reseed(&mut self, _seed : u32, _game : uint) {
+  }
+
+  //How much the player likes each legal action, higher is better, for players
+  //that have such a number. Only comparable between actions of one player.
+  fn action_scores(&mut self, _board : &Board) -> Option<Vec<(Action, f32)>> {
+    None
+  }
+
+  //A full analysis of board for players that search with an evaluation,
+  //so the evaluation behind the move can be shown
+  fn analysis(&mut self, _board : &Board) -> Option<Analysis> {
+    None
+  }
+}
+
+//Random numbers for game number game of a batch played from seed
+pub fn seeded_rng(seed : u32, game : uint) -> XorShiftRng {
+  //xorshift must not be seeded with all zeros
+  SeedableRng::from_seed([seed, 0x5eed, 0x2545f491, 0x9e3779b9 ^ game as u32])
+}
+
+//Random numbers for a player that is never reseeded
+pub fn random_rng() -> XorShiftRng {
+  task_rng().gen()
+}
+
+//Index and value of the tile that was added to after to make board
+pub fn spawned(after : &Board, board : &Board) -> (uint, int) {
+  for (i, (&a, &b)) in after.vec.iter().zip(board.vec.iter()).enumerate() {
+    if a != b {
+      return (i, b)
+    }
+  }
+  fail!("no tile was spawned")
+}
+
+//Counters collected while searching, for one move or added up over a game
+#[deriving(Show, Clone)]
+pub struct SearchStats {
+  pub searches : uint,
+  pub max_nodes : uint,
+  pub chance_nodes : uint,
+  pub leaf_evals : uint,
+  pub expanded : uint, //Nodes whose children were generated
+  pub children : uint,
+  pub depth_reached : uint,
+  pub time_ns : u64,
+  pub reuse_hits : uint, //Searches that started from the tree kept from the last move
+  pub reused : uint //Nodes whose children came from that tree instead of being generated
+}
+
+impl SearchStats {
+  pub fn new() -> SearchStats {
+    SearchStats {
+      searches : 0,
+      max_nodes : 0,
+      chance_nodes : 0,
+      leaf_evals : 0,
+      expanded : 0,
+      children : 0,
+      depth_reached : 0,
+      time_ns : 0,
+      reuse_hits : 0,
+      reused : 0
+    }
+  }
+
+  pub fn add(&mut self, other : &SearchStats) {
+    self.searches += other.searches;
+    self.max_nodes += other.max_nodes;
+    self.chance_nodes += other.chance_nodes;
+    self.leaf_evals += other.leaf_evals;
+    self.expanded += other.expanded;
+    self.children += other.children;
+    if other.depth_reached > self.depth_reached {
+      self.depth_reached = other.depth_reached;
+    }
+    self.time_ns += other.time_ns;
+    self.reuse_hits += other.reuse_hits;
+    self.reused += other.reused;
+  }
+
+  pub fn nodes(&self) -> uint {
+    self.max_nodes + self.chance_nodes
+  }
+
+  pub fn branching(&self) -> f32 {
+    if self.expanded == 0 { 0. } else { self.children as f32 / self.expanded as f32 }
+  }
+}
+
+//Estimates how good a board is, higher is better
+pub trait Evaluator {
+  fn evaluate(&self, board : &Board) -> f32;
+
+  //The terms that make up the evaluation, for evaluators built from terms
+  fn breakdown(&self, _board : &Board) -> Option<Score> {
+    None
+  }
+
+  //Values of many boards at once, for evaluators that are faster in batches
+  fn evaluate_batch(&self, boards : &Vec<Board>) -> Vec<f32> {
+    boards.iter().map(|board| self.evaluate(board)).collect()
+  }
+}
+
+impl<E : Evaluator + Send + Share> Evaluator for Arc<E> {
+  fn evaluate(&self, board : &Board) -> f32 {
+    (**self).evaluate(board)
+  }
+
+  fn breakdown(&self, board : &Board) -> Option<Score> {
+    (**self).breakdown(board)
+  }
+
+  fn evaluate_batch(&self, boards : &Vec<Board>) -> Vec<f32> {
+    (**self).evaluate_batch(boards)
+  }
+}
+
+//The hand written evaluation in herustic
+#[deriving(Show, Clone)]
+pub struct Heuristic {
+  weights : Weights
+}
+
+impl Heuristic {
+  pub fn new(weights : Weights) -> Heuristic {
+    Heuristic { weights : weights }
+  }
+
+  pub fn default() -> Heuristic {
+    Heuristic::new(Weights::default())
+  }
+}
+
+impl Evaluator for Heuristic {
+  fn evaluate(&self, board : &Board) -> f32 {
+    herustic(board, &self.weights).as_f32()
+  }
+
+  fn breakdown(&self, board : &Board) -> Option<Score> {
+    Some(herustic(board, &self.weights))
+  }
+}
+
+#[deriving(Clone, Send)]
+pub struct RandomPlayer {
+  rng : XorShiftRng
+}
+
+impl RandomPlayer {
+  pub fn new() -> RandomPlayer {
+    RandomPlayer { rng : random_rng() }
+  }
 }
 
-#[deriving(Show, Clone, Send)]
-pub struct RandomPlayer;
 impl AIPlayer for RandomPlayer {
-  fn next_action(&self, board : &Board) -> Action {
+  fn reseed(&mut self, seed : u32, game : uint) {
+    self.rng = seeded_rng(seed, game);
+  }
+
+  fn next_action(&mut self, board : &Board) -> Action {
     let actions = board.get_actions();
-    let action = rand::task_rng().choose(actions.as_slice());
+    let action = self.rng.choose(actions.as_slice());
     *action.unwrap()
   }
 }
 
+//Takes the move that merges the most
+#[deriving(Clone)]
+pub struct GreedyScore {
+  rng : XorShiftRng //Breaks ties
+}
+
+impl GreedyScore {
+  pub fn new() -> GreedyScore {
+    GreedyScore { rng : random_rng() }
+  }
+}
+
+impl AIPlayer for GreedyScore {
+  fn reseed(&mut self, seed : u32, game : uint) {
+    self.rng = seeded_rng(seed, game);
+  }
+
+  fn next_action(&mut self, board : &Board) -> Action {
+    let mut best = vec!();
+    let mut best_score = -1;
+    for &action in board.get_actions().iter() {
+      let (_, score) = board.move_and_score(action);
+      if score > best_score {
+        best_score = score;
+        best = vec!(action);
+      } else if score == best_score {
+        best.push(action);
+      }
+    }
+    *self.rng.choose(best.as_slice()).unwrap()
+  }
+}
+
+//Takes the move whose resulting board evaluates best, one ply only
+#[deriving(Show, Clone)]
+pub struct Greedy<E> {
+  evaluator : E
+}
+
+impl<E : Evaluator> Greedy<E> {
+  pub fn new(evaluator : E) -> Greedy<E> {
+    Greedy { evaluator : evaluator }
+  }
+}
+
+impl<E : Evaluator> AIPlayer for Greedy<E> {
+  fn next_action(&mut self, board : &Board) -> Action {
+    let inf : f32 = Float::infinity();
+    let mut best_value = -inf;
+    let mut best = None;
+    for &action in board.get_actions().iter() {
+      let value = self.evaluator.evaluate(&board.move(action));
+      if value > best_value {
+        best_value = value;
+        best = Some(action);
+      }
+    }
+    best.unwrap()
+  }
+}
+
+//Takes the first legal move in a fixed order
+#[deriving(Show, Clone)]
+pub struct FixedPriority {
+  order : Vec<Action>
+}
+
+impl FixedPriority {
+  pub fn new(order : Vec<Action>) -> FixedPriority {
+    FixedPriority { order : order }
+  }
+
+  //The corner strategy, keep everything down and left and only go up when forced
+  pub fn corner() -> FixedPriority {
+    FixedPriority::new(vec!(Down, Left, Right, Up))
+  }
+}
+
+impl AIPlayer for FixedPriority {
+  fn next_action(&mut self, board : &Board) -> Action {
+    let actions = board.get_actions();
+    *self.order.iter().find(|a| actions.contains(*a)).unwrap()
+  }
+}
+
+//Repeats a pattern of moves, skipping the ones that are not legal.
+//Falls back to a random move when nothing in the pattern is.
+#[deriving(Clone)]
+pub struct Cyclic {
+  pattern : Vec<Action>,
+  next : uint,
+  rng : XorShiftRng
+}
+
+impl Cyclic {
+  pub fn new(pattern : Vec<Action>) -> Cyclic {
+    Cyclic { pattern : pattern, next : 0, rng : random_rng() }
+  }
+}
+
+impl AIPlayer for Cyclic {
+  fn new_game(&mut self) {
+    self.next = 0;
+  }
+
+  fn reseed(&mut self, seed : u32, game : uint) {
+    self.rng = seeded_rng(seed, game);
+  }
+
+  fn next_action(&mut self, board : &Board) -> Action {
+    let actions = board.get_actions();
+    for _ in range(0, self.pattern.len()) {
+      let action = *self.pattern.get(self.next);
+      self.next = (self.next + 1) % self.pattern.len();
+      if actions.contains(&action) {
+        return action
+      }
+    }
+    *self.rng.choose(actions.as_slice()).unwrap()
+  }
+}
+
+//Plays a random move with probability epsilon, otherwise asks player
+#[deriving(Clone)]
+pub struct EpsilonGreedy<T> {
+  player : T,
+  epsilon : f32,
+  rng : XorShiftRng
+}
+
+impl<T : AIPlayer> EpsilonGreedy<T> {
+  pub fn new(player : T, epsilon : f32) -> EpsilonGreedy<T> {
+    EpsilonGreedy { player : player, epsilon : epsilon, rng : random_rng() }
+  }
+}
+
+impl<T : AIPlayer> AIPlayer for EpsilonGreedy<T> {
+  fn new_game(&mut self) {
+    self.player.new_game();
+  }
+
+  //The wrapped player gets its own stream so it does not share draws with ours
+  fn reseed(&mut self, seed : u32, game : uint) {
+    self.rng = seeded_rng(seed, game);
+    self.player.reseed(seed + 1, game);
+  }
+
+  fn next_action(&mut self, board : &Board) -> Action {
+    let r : f32 = self.rng.gen();
+    if r < self.epsilon {
+      *self.rng.choose(board.get_actions().as_slice()).unwrap()
+    } else {
+      self.player.next_action(board)
+    }
+  }
+
+  fn observe(&mut self, action : Action, spawn : (uint, int), board : &Board) {
+    self.player.observe(action, spawn, board);
+  }
+
+  fn end_game(&mut self, report : &Report) {
+    self.player.end_game(report);
+  }
+}
+
 #[deriving(Clone, Show)]
 pub enum Move {
   Direction(Action),
@@ -64,205 +416,791 @@ impl State {
   }
 }
 
-#[deriving(Show)]
+#[deriving(Show, Clone)]
 pub struct Score {
   empty_count : f32,
   near_game_over : f32,
   squared_log : f32,
   best_not_in_center : f32,
-  smooth_rating : f32
+  smooth_rating : f32,
+  monotonicity : f32,
+  snake : f32,
+  merges : f32
 }
 
 impl Score {
+  //Raw terms in the order of WEIGHT_NAMES, each multiplied by its weight
+  pub fn weighted(t : &[f32, ..8], weights : &Weights) -> Score {
+    Score {
+      empty_count : t[0] * weights.empty_count,
+      near_game_over : t[1] * weights.near_game_over,
+      squared_log : t[2] * weights.squared_log,
+      best_not_in_center : t[3] * weights.best_not_in_center,
+      smooth_rating : t[4] * weights.smooth_rating,
+      monotonicity : t[5] * weights.monotonicity,
+      snake : t[6] * weights.snake,
+      merges : t[7] * weights.merges
+    }
+  }
+
   pub fn as_f32(&self) -> f32{
     self.empty_count +
       self.near_game_over +
       self.squared_log +
       self.best_not_in_center +
-      self.smooth_rating
+      self.smooth_rating +
+      self.monotonicity +
+      self.snake +
+      self.merges
   }
 }
 
+static WEIGHT_NAMES : [&'static str, ..8] = ["empty_count", "near_game_over",
+  "squared_log", "best_not_in_center", "smooth_rating", "monotonicity", "snake", "merges"];
+
+//Multiplier for each term of herustic
+#[deriving(Show, Clone)]
+pub struct Weights {
+  pub empty_count : f32,
+  pub near_game_over : f32,
+  pub squared_log : f32,
+  pub best_not_in_center : f32,
+  pub smooth_rating : f32,
+  pub monotonicity : f32,
+  pub snake : f32,
+  pub merges : f32
+}
+
+impl Weights {
+  pub fn default() -> Weights {
+    Weights {
+      empty_count : 2.,
+      near_game_over : 1.,
+      squared_log : 1.,
+      best_not_in_center : 2.,
+      smooth_rating : 1.,
+      monotonicity : 1.,
+      snake : 0.05,
+      merges : 1.
+    }
+  }
+
+  //In the same order as WEIGHT_NAMES
+  pub fn as_vec(&self) -> Vec<f32> {
+    vec!(self.empty_count, self.near_game_over, self.squared_log, self.best_not_in_center,
+         self.smooth_rating, self.monotonicity, self.snake, self.merges)
+  }
+
+  pub fn from_vec(v : &Vec<f32>) -> Weights {
+    Weights {
+      empty_count : *v.get(0),
+      near_game_over : *v.get(1),
+      squared_log : *v.get(2),
+      best_not_in_center : *v.get(3),
+      smooth_rating : *v.get(4),
+      monotonicity : *v.get(5),
+      snake : *v.get(6),
+      merges : *v.get(7)
+    }
+  }
+
+  //One "name value" pair per line, lines starting with # are ignored.
+  //Any weight that is left out keeps its default.
+  pub fn load(path : &Path) -> IoResult<Weights> {
+    let mut reader = BufferedReader::new(try!(File::open(path)));
+    let mut v = Weights::default().as_vec();
+    for line in reader.lines() {
+      let line = try!(line);
+      let words : Vec<&str> = line.as_slice().words().collect();
+      if words.len() != 2 || words.get(0).starts_with("#") {
+        continue
+      }
+      let indx = WEIGHT_NAMES.iter().position(|name| name == words.get(0));
+      match (indx, from_str::<f32>(*words.get(1))) {
+        (Some(indx), Some(value)) => *v.get_mut(indx) = value,
+        _ => return Err(IoError { kind : InvalidInput, desc : "bad weight line",
+                                  detail : Some(line.clone()) })
+      }
+    }
+    Ok(Weights::from_vec(&v))
+  }
+
+  pub fn save(&self, path : &Path) -> IoResult<()> {
+    let mut file = try!(File::create(path));
+    for (name, value) in WEIGHT_NAMES.iter().zip(self.as_vec().iter()) {
+      try!(writeln!(file, "{} {}", name, value));
+    }
+    Ok(())
+  }
+}
+
+//Positional weight of a space for a snake running from the top left corner
+//along the rows. On a 4x4 board:
+//  15 14 13 12
+//   8  9 10 11
+//   7  6  5  4
+//   0  1  2  3
+fn snake_weight(x : int, y : int, width : int, height : int) -> f32 {
+  let row = height - 1 - y; //counted from the bottom
+  (row * width + if row % 2 == 0 { x } else { width - 1 - x }) as f32
+}
+
+//log2 of a tile as a float, empty spaces are 0
+pub fn rank(value : int) -> f32 {
+  exponent(value) as f32
+}
+
+//Penalty for a line of tiles that is not sorted in either direction
+pub fn line_monotonicity(line : &[int]) -> f32 {
+  let mut inc = 0.;
+  let mut dec = 0.;
+  for i in range(1u, line.len()) {
+    let a = rank(line[i-1]);
+    let b = rank(line[i]);
+    if a > b {
+      dec += a - b;
+    } else {
+      inc += b - a;
+    }
+  }
+  -(if inc < dec { inc } else { dec })
+}
+
+//Number of neighbouring pairs that could merge in a line of tiles
+pub fn line_merges(line : &[int]) -> f32 {
+  let mut merges = 0.;
+  for i in range(1u, line.len()) {
+    if line[i] != 0 && line[i] == line[i-1] {
+      merges += 1.;
+    }
+  }
+  merges
+}
+
+//What ExpectiMax maximises. The evaluator still estimates positions at the
+//search horizon, the objective decides how that estimate, finished games and
+//the moves along the way are counted.
+#[deriving(Clone, Show)]
+pub enum SearchObjective {
+  //Expected evaluation at the horizon
+  MaxEvaluation,
+  //Expected points merged, plus the evaluation at the horizon
+  MaxScore,
+  //Chance of making a tile this large, which ends the search as a win.
+  //Positions at the horizon are worth their evaluation squashed into 0..1.
+  TargetTile(int),
+  //Expected number of moves before the game ends, with the squashed
+  //evaluation at the horizon as a bonus of less than one move
+  MaxSurvival
+}
+
+//Scale of the evaluations squashed into 0..1 by the objectives that need it
+static SQUASH_SCALE : f32 = 50.;
+
+//What a finished game is worth under MaxScore, below any live position
+//whatever its evaluation and the points merged on the way to it
+static GAME_OVER_VALUE : f32 = -1e7;
+
+fn squash(value : f32) -> f32 {
+  1. / (1. + (-value / SQUASH_SCALE).exp())
+}
+
+//A position in an ExpectiMax search. The children of a max node are its
+//moves, those of a chance node the spawns that were sampled. A node without
+//children has not been expanded.
 #[deriving(Clone)]
-pub struct ExpectiMax {
+struct Node {
+  action : Move, //Action to get to this board
+  board : Board,
+  reward : int, //Points merged by the move to this board
+  children : Vec<Node>
+}
+
+impl Node {
+  fn new(action : Move, board : Board, reward : int) -> Node {
+    Node { action : action, board : board, reward : reward, children : vec!() }
+  }
+
+  //The subtree after playing action and then spawn, if the search sampled that spawn
+  fn follow(self, action : Action, spawn : (uint, int)) -> Option<Node> {
+    let (indx, tile) = spawn;
+    let chance = self.children.move_iter().find(|c| match c.action {
+      Direction(a) => a == action,
+      _ => false
+    });
+    chance.and_then(|c| c.children.move_iter().find(|n| match n.action {
+      Space((i, t, _)) => i == indx && t == tile,
+      _ => false
+    }))
+  }
+
+  fn level_sizes(&self, depth : uint, sizes : &mut Vec<uint>) {
+    if sizes.len() == depth {
+      sizes.push(0);
+    }
+    *sizes.get_mut(depth) += 1;
+    for child in self.children.iter() {
+      child.level_sizes(depth + 1, sizes);
+    }
+  }
+
+  //Forget the children of every node at depth
+  fn prune(&mut self, depth : uint) {
+    if depth == 0 {
+      self.children.clear();
+    } else {
+      for child in self.children.mut_iter() {
+        child.prune(depth - 1);
+      }
+    }
+  }
+
+  //Drop the deepest levels until at most max_nodes are left
+  fn limit(&mut self, max_nodes : uint) {
+    let mut sizes = vec!();
+    self.level_sizes(0, &mut sizes);
+    let mut total = 0;
+    for (depth, &size) in sizes.iter().enumerate() {
+      total += size;
+      if total > max_nodes {
+        self.prune(if depth == 0 { 0 } else { depth - 1 });
+        return
+      }
+    }
+  }
+}
+
+#[deriving(Clone)]
+pub struct ExpectiMax<E> {
   max_depth : uint,
-  num_expecti : uint
+  num_expecti : uint,
+  evaluator : E,
+  objective : SearchObjective,
+  //Most nodes kept between moves, None to search every move from scratch
+  tree_limit : Option<uint>,
+  //Tree of the last search, moved down to the current position by observe
+  tree : Option<Node>,
+  //Picks the spawns a chance node samples
+  rng : XorShiftRng
 }
 
-impl AIPlayer for ExpectiMax {
-  fn next_action(&self, board : &Board) -> Action {
-    let cur_state = State::new(Start, 0, board.clone());
-    let (s, _) = self.max_layer(&cur_state);
-    s.action.dir()
+impl<E : Evaluator> AIPlayer for ExpectiMax<E> {
+  fn new_game(&mut self) {
+    self.tree = None;
+  }
+
+  fn reseed(&mut self, seed : u32, game : uint) {
+    self.rng = seeded_rng(seed, game);
   }
+
+  fn next_action(&mut self, board : &Board) -> Action {
+    let (action, _) = self.search(board);
+    action
+  }
+
+  fn search(&mut self, board : &Board) -> (Action, SearchStats) {
+    let start = time::precise_time_ns();
+    let mut stats = SearchStats::new();
+    let mut root = match self.tree.take() {
+      Some(tree) => if tree.board == *board {
+        stats.reuse_hits = 1;
+        tree
+      } else {
+        Node::new(Start, board.clone(), 0)
+      },
+      None => Node::new(Start, board.clone(), 0)
+    };
+    let objective = self.objective_at(board);
+    let mut rng = self.rng.clone();
+    let keep = self.tree_limit.is_some();
+    let line = self.max_node(&objective, &mut root, 0, keep, &mut stats, &mut rng);
+    self.rng = rng;
+    match self.tree_limit {
+      Some(max_nodes) => {
+        root.limit(max_nodes);
+        self.tree = Some(root);
+      }
+      None => ()
+    }
+    stats.searches = 1;
+    stats.time_ns = time::precise_time_ns() - start;
+    (line.moves.get(0).dir(), stats)
+  }
+
+  fn observe(&mut self, action : Action, spawn : (uint, int), _board : &Board) {
+    self.tree = match self.tree.take() {
+      Some(tree) => tree.follow(action, spawn),
+      None => None
+    };
+  }
+
+  fn action_scores(&mut self, board : &Board) -> Option<Vec<(Action, f32)>> {
+    let mut rng = self.rng.clone();
+    let analysis = self.analyse_with(board, &mut rng);
+    self.rng = rng;
+    Some(analysis.actions.iter().map(|a| (a.action, a.value)).collect())
+  }
+
+  fn analysis(&mut self, board : &Board) -> Option<Analysis> {
+    let mut rng = self.rng.clone();
+    let analysis = self.analyse_with(board, &mut rng);
+    self.rng = rng;
+    Some(analysis)
+  }
+}
+
+//The value of a position and the line of play the search expects from it.
+//At chance layers the line follows the most likely of the sampled spawns.
+#[deriving(Show, Clone)]
+pub struct Line {
+  pub value : f32,
+  pub moves : Vec<Move>,
+  pub leaf : Board
 }
 
-impl ExpectiMax {
-  pub fn new(max_depth : uint, num_expecti : uint) -> ExpectiMax {
-    ExpectiMax { max_depth : max_depth , num_expecti : num_expecti}
+impl Line {
+  pub fn leaf(value : f32, board : &Board) -> Line {
+    Line { value : value, moves : vec!(), leaf : board.clone() }
   }
+}
+
+#[deriving(Show, Clone)]
+pub struct ActionAnalysis {
+  pub action : Action,
+  pub value : f32,
+  //Breakdown of the evaluation at the end of the principal variation
+  pub score : Option<Score>,
+  //Starts with action
+  pub pv : Vec<Move>
+}
 
-  pub fn max_layer(&self, s : &State) -> (State, f32) {
-    let actions_vec = s.board.get_actions();
-    if s.depth == self.max_depth || actions_vec.len() == 0 {
-      let score = ExpectiMax::herustic(s);
-      (s.clone(), score.as_f32())
+//Every legal action from a position, best first
+#[deriving(Show, Clone)]
+pub struct Analysis {
+  pub actions : Vec<ActionAnalysis>,
+  pub stats : SearchStats
+}
+
+impl Analysis {
+  pub fn best<'a>(&'a self) -> &'a ActionAnalysis {
+    self.actions.get(0)
+  }
+
+  pub fn get<'a>(&'a self, action : Action) -> Option<&'a ActionAnalysis> {
+    self.actions.iter().find(|a| a.action == action)
+  }
+
+  //How far ahead the best action is of the second best
+  pub fn margin(&self) -> f32 {
+    if self.actions.len() < 2 {
+      0.
     } else {
-      let actions = actions_vec.iter();
+      self.actions.get(0).value - self.actions.get(1).value
+    }
+  }
+}
 
-      let states : Vec<State> = FromIterator::from_iter(
-        actions.map(|&action| {
-          State::new(Direction(action), s.depth + 1, s.board.move(action))
-        }));
+impl<E : Evaluator> ExpectiMax<E> {
+  pub fn new(max_depth : uint, num_expecti : uint, evaluator : E) -> ExpectiMax<E> {
+    ExpectiMax::with_objective(max_depth, num_expecti, evaluator, MaxEvaluation)
+  }
 
-      let results = states.iter().map(|next_state| {
-        self.expecti_layer(next_state)
-      });
+  //num_expecti is at least 1, a chance node needs a spawn to average over
+  pub fn with_objective(max_depth : uint, num_expecti : uint, evaluator : E,
+                        objective : SearchObjective) -> ExpectiMax<E> {
+    let num_expecti = if num_expecti == 0 { 1 } else { num_expecti };
+    ExpectiMax { max_depth : max_depth , num_expecti : num_expecti, evaluator : evaluator,
+                 objective : objective, tree_limit : None, tree : None, rng : random_rng() }
+  }
 
-      let inf : f32 = Float::infinity();
-      let mut max_score : f32 = -inf;
-      let mut max_idx : Option<uint> = None;
+  //Keep up to max_nodes of the search tree between moves. The subtree under
+  //the move played and the spawn that followed becomes the next root, so its
+  //nodes do not have to be generated again.
+  pub fn reusing(mut self, max_nodes : uint) -> ExpectiMax<E> {
+    self.tree_limit = Some(max_nodes);
+    self
+  }
 
-      for (idx, score) in results.enumerate() {
-        if score > max_score {
-          max_score = score;
-          max_idx = Some(idx);
-        }
-      }
+  //The objective for a search from root. Once the target tile is on the
+  //board every line has won, so the search goes back to the evaluation.
+  fn objective_at(&self, root : &Board) -> SearchObjective {
+    match self.objective {
+      TargetTile(target) if root.get_best_tile() >= target => MaxEvaluation,
+      ref objective => objective.clone()
+    }
+  }
 
-      (states.get(max_idx.unwrap()).clone(), max_score)
+  //True if board ends the search whatever its depth
+  fn won(&self, objective : &SearchObjective, board : &Board) -> bool {
+    match *objective {
+      TargetTile(target) => board.get_best_tile() >= target,
+      _ => false
     }
   }
 
-  pub fn expecti_layer(&self, s : &State) -> f32 {
-    let actions_vec = s.board.get_actions();
-    if s.depth == self.max_depth || actions_vec.len() == 0 {
-      let score = ExpectiMax::herustic(s);
-      score.as_f32()
-    } else {
+  //What a move that merged reward points adds to a line
+  fn move_value(&self, objective : &SearchObjective, reward : int) -> f32 {
+    match *objective {
+      MaxScore => reward as f32,
+      MaxSurvival => 1.,
+      _ => 0.
+    }
+  }
 
-      let num_empty = s.board.count_empty() as f32;
+  //What a leaf is worth under the objective, given its evaluation
+  fn leaf_value(&self, objective : &SearchObjective, board : &Board, value : f32) -> f32 {
+    match *objective {
+      MaxEvaluation => value,
+      MaxScore => if board.get_actions().len() == 0 { GAME_OVER_VALUE } else { value },
+      TargetTile(_) if self.won(objective, board) => 1.,
+      _ => if board.get_actions().len() == 0 { 0. } else { squash(value) }
+    }
+  }
 
-      let action_2 = s.board.vec.iter().enumerate().filter_map( |x| {
-        match x {
-          (index, &0) => Some((index, 2, 0.9 / num_empty)),
-          (_, _) => None
-        }
-      });
-      //TODO remove code duplciation..
-      let a = s.board.vec.iter().enumerate().filter_map( |x| {
-        match x {
-          (index, &0) => Some((index, 2, 0.9 / num_empty)),
-          (_, _) => None
-        }
-      });
-      let empty = s.board.vec.iter().enumerate().filter_map( |x| {
-        match x {
-          (_, _) => None
-        }
+  //Value of every legal action from board rather than just the best
+  pub fn analyse(&self, board : &Board) -> Analysis {
+    self.analyse_with(board, &mut random_rng())
+  }
+
+  //Same as analyse with the spawns sampled from rng
+  pub fn analyse_with(&self, board : &Board, rng : &mut XorShiftRng) -> Analysis {
+    let start = time::precise_time_ns();
+    let mut stats = SearchStats::new();
+    let objective = self.objective_at(board);
+    let mut actions = vec!();
+    for &action in board.get_actions().iter() {
+      let (after, reward) = board.move_and_score(action);
+      let mut node = Node::new(Direction(action), after, reward);
+      let mut line = self.chance_node(&objective, &mut node, 1, false, &mut stats, rng);
+      line.value += self.move_value(&objective, reward);
+      line.moves.insert(0, Direction(action));
+      actions.push(ActionAnalysis {
+        action : action,
+        value : line.value,
+        score : self.evaluator.breakdown(&line.leaf),
+        pv : line.moves
       });
+    }
+    actions.sort_by(|a, b| {
+      if a.value > b.value { Less } else if a.value < b.value { Greater } else { Equal }
+    });
+    stats.searches = 1;
+    stats.time_ns = time::precise_time_ns() - start;
+    Analysis { actions : actions, stats : stats }
+  }
 
-      //TODO make this sampling actually correct
-      let actions = if num_empty <= (self.num_expecti-1) as f32 {
-        action_2.chain(empty.map(|a| a))
+  //Children one ply above the horizon are all leaves, so they are evaluated
+  //together in one batch. chance says whether they are chance nodes.
+  fn leaf_layer(&self, objective : &SearchObjective, nodes : &Vec<Node>, depth : uint, chance : bool,
+                stats : &mut SearchStats) -> Vec<Line> {
+    for _ in nodes.iter() {
+      if chance {
+        stats.chance_nodes += 1;
       } else {
-        action_2.chain(a.map(|(indx, _, _)| (indx, 4, 0.1 / num_empty)))
-      };
+        stats.max_nodes += 1;
+      }
+      if depth > stats.depth_reached {
+        stats.depth_reached = depth;
+      }
+      stats.leaf_evals += 1;
+    }
+    let boards : Vec<Board> = nodes.iter().map(|n| n.board.clone()).collect();
+    let values = self.evaluator.evaluate_batch(&boards);
+    values.iter().zip(boards.iter()).map(|(&value, board)| {
+      Line::leaf(self.leaf_value(objective, board, value), board)
+    }).collect()
+  }
 
-      let num_actions = (num_empty * 2.) as uint;
-      let num_samps = self.num_expecti;
+  pub fn max_layer(&self, s : &State, stats : &mut SearchStats) -> Line {
+    let objective = self.objective_at(&s.board);
+    self.max_node(&objective, &mut Node::new(s.action.clone(), s.board.clone(), 0), s.depth, false,
+                  stats, &mut random_rng())
+  }
+
+  pub fn expecti_layer(&self, s : &State, stats : &mut SearchStats) -> Line {
+    let objective = self.objective_at(&s.board);
+    self.chance_node(&objective, &mut Node::new(s.action.clone(), s.board.clone(), 0), s.depth, false,
+                     stats, &mut random_rng())
+  }
+
+  //keep says whether node's children stay in the tree after it has been
+  //scored. Without tree reuse they are dropped straight away so the search
+  //only ever holds the nodes along the current path.
+  fn max_node(&self, objective : &SearchObjective, node : &mut Node, depth : uint, keep : bool,
+              stats : &mut SearchStats, rng : &mut XorShiftRng) -> Line {
+    stats.max_nodes += 1;
+    if depth > stats.depth_reached {
+      stats.depth_reached = depth;
+    }
+    let actions_vec = node.board.get_actions();
+    if depth == self.max_depth || actions_vec.len() == 0 || (depth > 0 && self.won(objective, &node.board)) {
+      stats.leaf_evals += 1;
+      Line::leaf(self.leaf_value(objective, &node.board, self.evaluator.evaluate(&node.board)), &node.board)
+    } else {
+      if node.children.len() == 0 {
+        let mut children = vec!();
+        for &action in actions_vec.iter() {
+          let (after, reward) = node.board.move_and_score(action);
+          children.push(Node::new(Direction(action), after, reward));
+        }
+        stats.expanded += 1;
+        stats.children += children.len();
+        node.children = children;
+      } else {
+        stats.reused += 1;
+      }
 
-      let sampled_actions = if num_actions > num_samps {
-        task_rng().sample(actions, num_samps)
+      let lines = if depth + 1 == self.max_depth {
+        self.leaf_layer(objective, &node.children, depth + 1, true, stats)
       } else {
-        task_rng().sample(actions, num_actions)
+        let mut lines = vec!();
+        for child in node.children.mut_iter() {
+          lines.push(self.chance_node(objective, child, depth + 1, keep, stats, rng));
+        }
+        lines
       };
 
-      let states : Vec<State> = FromIterator::from_iter(
-        sampled_actions.iter().map(|&action| {
-          State::new(Space(action), s.depth + 1, s.board.add_space(action))
-        }));
+      let inf : f32 = Float::infinity();
+      let mut max_score : f32 = -inf;
+      let mut max_line : Option<Line> = None;
 
-      let results = states.iter().map(|next_state| {
-        self.max_layer(next_state)
-      });
+      for (child, mut line) in node.children.iter().zip(lines.move_iter()) {
+        line.value += self.move_value(objective, child.reward);
+        if line.value > max_score {
+          max_score = line.value;
+          line.moves.insert(0, child.action.clone());
+          max_line = Some(line);
+        }
+      }
+      if !keep {
+        node.children = vec!();
+      }
 
+      max_line.unwrap()
+    }
+  }
+
+  fn chance_node(&self, objective : &SearchObjective, node : &mut Node, depth : uint, keep : bool,
+                 stats : &mut SearchStats, rng : &mut XorShiftRng) -> Line {
+    stats.chance_nodes += 1;
+    if depth > stats.depth_reached {
+      stats.depth_reached = depth;
+    }
+    let actions_vec = node.board.get_actions();
+    if depth == self.max_depth || actions_vec.len() == 0 || (depth > 0 && self.won(objective, &node.board)) {
+      stats.leaf_evals += 1;
+      Line::leaf(self.leaf_value(objective, &node.board, self.evaluator.evaluate(&node.board)), &node.board)
+    } else {
+      if node.children.len() == 0 {
+        let children : Vec<Node> = self.sample_spawns(&node.board, rng).iter().map(|&action| {
+          Node::new(Space(action), node.board.add_space(action), 0)
+        }).collect();
+        node.children = children;
+        stats.expanded += 1;
+        stats.children += node.children.len();
+      } else {
+        stats.reused += 1;
+      }
+
+      let lines = if depth + 1 == self.max_depth {
+        self.leaf_layer(objective, &node.children, depth + 1, false, stats)
+      } else {
+        let mut lines = vec!();
+        for child in node.children.mut_iter() {
+          lines.push(self.max_node(objective, child, depth + 1, keep, stats, rng));
+        }
+        lines
+      };
 
       let mut cum_score = 0.;
       let mut cum_prob = 0.;
-      for (idx, (_, score)) in results.enumerate() {
-        let state = states.get(idx);
-        let (_, _, prob) = state.action.space();
-        cum_score += score * prob;
+      let mut likeliest : Option<(f32, Line)> = None;
+      for (child, mut line) in node.children.iter().zip(lines.move_iter()) {
+        let (_, _, prob) = child.action.space();
+        cum_score += line.value * prob;
         cum_prob += prob;
+        let more_likely = match likeliest {
+          Some((best, _)) => prob > best,
+          None => true
+        };
+        if more_likely {
+          line.moves.insert(0, child.action.clone());
+          likeliest = Some((prob, line));
+        }
+      }
+      if !keep {
+        node.children = vec!();
       }
-      cum_score / cum_prob
+      let (_, mut line) = likeliest.unwrap();
+      line.value = cum_score / cum_prob;
+      line
     }
   }
 
-  pub fn herustic( s : &State) -> Score{
-    //Want empty spaces
-    let empty_count = s.board.count_empty() as f32;
+  //The spawns a chance node averages over
+  fn sample_spawns(&self, board : &Board, rng : &mut XorShiftRng) -> Vec<(uint, int, f32)> {
+    let num_empty = board.count_empty() as f32;
 
-    //near game end
-    let near_game_over = if empty_count == 0. {
-      -100.
-    } else if empty_count < 3. {
-      -7.*(3.-empty_count)
+    let action_2 = board.vec.iter().enumerate().filter_map( |x| {
+      match x {
+        (index, &0) => Some((index, 2, 0.9 / num_empty)),
+        (_, _) => None
+      }
+    });
+    //TODO remove code duplciation..
+    let a = board.vec.iter().enumerate().filter_map( |x| {
+      match x {
+        (index, &0) => Some((index, 2, 0.9 / num_empty)),
+        (_, _) => None
+      }
+    });
+    let empty = board.vec.iter().enumerate().filter_map( |x| {
+      match x {
+        (_, _) => None
+      }
+    });
+
+    //TODO make this sampling actually correct
+    let actions = if num_empty <= (self.num_expecti-1) as f32 {
+      action_2.chain(empty.map(|a| a))
     } else {
-      0.
+      action_2.chain(a.map(|(indx, _, _)| (indx, 4, 0.1 / num_empty)))
     };
 
-    //strive for large numbers
-    let squared_sum = s.board.vec.iter().map(|&x| x*x).sum();
-    let squared_log = (squared_sum as f32).log2();
-
-    //large numbers not in the center
-    let mut best_not_in_center = 0.;
-    let best = s.board.get_best_tile();
-    let coords = vec!(Cord(1,1), Cord(1, 2), Cord(2, 1), Cord(2, 2));
-    for &cord in coords.iter() {
-      if best == s.board.get(cord) {
-        best_not_in_center -= 6.;
+    let num_actions = (num_empty * 2.) as uint;
+    let num_samps = self.num_expecti;
+
+    if num_actions > num_samps {
+      rng.sample(actions, num_samps)
+    } else {
+      rng.sample(actions, num_actions)
+    }
+  }
+}
+
+pub fn near_game_over(empty_count : f32) -> f32 {
+  if empty_count == 0. {
+    -100.
+  } else if empty_count < 3. {
+    -7.*(3.-empty_count)
+  } else {
+    0.
+  }
+}
+
+pub fn best_not_in_center(board : &Board) -> f32 {
+  best_tile_not_in_center(board, board.get_best_tile())
+}
+
+//Same as best_not_in_center for a caller that already knows the best tile
+pub fn best_tile_not_in_center(board : &Board, best : int) -> f32 {
+  let mut penalty = 0.;
+  //every space off the edges, the middle four on a 4x4 board
+  for y in range(1, board.height - 1) {
+    for x in range(1, board.width - 1) {
+      let tile = board.get(Cord(x, y));
+      if best == tile {
+        penalty -= 6.;
+      }
+      if best/2 == tile {
+        penalty -= 4.;
       }
-      if best/2 == s.board.get(cord) {
-        best_not_in_center -= 4.;
+    }
+  }
+  penalty
+}
+
+//Best fit of the ranks of a board, in the order of Board::vec, to a snake
+//from any corner. Like Cord::transform, but transposing only square boards.
+pub fn snake(ranks : &[f32], width : int, height : int) -> f32 {
+  let mut snake = 0.;
+  for sym in range(0u, 8) {
+    if sym & 4 != 0 && width != height {
+      continue
+    }
+    let mut total = 0.;
+    for y in range(0, height) {
+      for x in range(0, width) {
+        let (sx, sy) = if sym & 4 != 0 { (y, x) } else { (x, y) };
+        let sx = if sym & 1 != 0 { width - 1 - sx } else { sx };
+        let sy = if sym & 2 != 0 { height - 1 - sy } else { sy };
+        total += snake_weight(sx, sy, width, height) * ranks[(x + y*width) as uint];
       }
     }
+    if total > snake {
+      snake = total;
+    }
+  }
+  snake
+}
 
-    //How smooth board is
-    let mut smooth = 0.;
-    for x in range(0, 3) {
-      for y in range(0, 4) {
-        let b = match s.board.get(Cord(x,y)) as f32 { 0. => 1., x => x };
-        let d = match s.board.get(Cord(x+1,y)) as f32 {0. => 1., x => x };
-        if b != 0. && d != 0. {
-          let factor = (b.log2() - d.log2()).abs();
-          smooth -= factor;
-        }
+pub fn herustic(board : &Board, weights : &Weights) -> Score {
+  //Want empty spaces
+  let empty_count = board.count_empty() as f32;
+
+  //near game end
+  let near_game_over = near_game_over(empty_count);
+
+  //strive for large numbers
+  let squared_sum = board.vec.iter().map(|&x| x*x).sum();
+  let squared_log = (squared_sum as f32).log2();
+
+  //large numbers not in the center
+  let best_not_in_center = best_not_in_center(board);
+
+  //How smooth board is
+  let mut smooth = 0.;
+  for x in range(0, board.width - 1) {
+    for y in range(0, board.height) {
+      let b = match board.get(Cord(x,y)) as f32 { 0. => 1., x => x };
+      let d = match board.get(Cord(x+1,y)) as f32 {0. => 1., x => x };
+      if b != 0. && d != 0. {
+        let factor = (b.log2() - d.log2()).abs();
+        smooth -= factor;
       }
     }
-    for y in range(0, 3) {
-      for x in range(0, 4) {
-        let b = match s.board.get(Cord(x,y)) as f32 { 0. => 1., x => x };
-        let d = match s.board.get(Cord(x,y+1)) as f32 {0. => 1., x => x };
-        if b != 0. && d != 0. {
-          let factor = (b.log2() - d.log2()).abs();
-          smooth -= factor;
-        }
+  }
+  for y in range(0, board.height - 1) {
+    for x in range(0, board.width) {
+      let b = match board.get(Cord(x,y)) as f32 { 0. => 1., x => x };
+      let d = match board.get(Cord(x,y+1)) as f32 {0. => 1., x => x };
+      if b != 0. && d != 0. {
+        let factor = (b.log2() - d.log2()).abs();
+        smooth -= factor;
       }
     }
-    let smooth_rating = -(smooth * smooth) / 200.0;
+  }
+  let smooth_rating = -(smooth * smooth) / 200.0;
 
-    let score = Score{
-      empty_count : empty_count*2.,
-      near_game_over : near_game_over,
-      squared_log : squared_log,
-      best_not_in_center : best_not_in_center * 2.,
-      smooth_rating : smooth_rating
-    };
-    score
+  //rows and columns sorted towards an edge
+  let mut monotonicity = 0.;
+  let mut merges = 0.;
+  for y in range(0, board.height) {
+    let row : Vec<int> = range(0, board.width).map(|x| board.get(Cord(x, y))).collect();
+    monotonicity += line_monotonicity(row.as_slice());
+    merges += line_merges(row.as_slice());
+  }
+  for x in range(0, board.width) {
+    let col : Vec<int> = range(0, board.height).map(|y| board.get(Cord(x, y))).collect();
+    monotonicity += line_monotonicity(col.as_slice());
+    merges += line_merges(col.as_slice());
   }
+
+  //large tiles laid out in a snake from the best corner
+  let ranks : Vec<f32> = board.vec.iter().map(|&tile| rank(tile)).collect();
+  let snake = snake(ranks.as_slice(), board.width, board.height);
+
+  let score = Score{
+    empty_count : empty_count * weights.empty_count,
+    near_game_over : near_game_over * weights.near_game_over,
+    squared_log : squared_log * weights.squared_log,
+    best_not_in_center : best_not_in_center * weights.best_not_in_center,
+    smooth_rating : smooth_rating * weights.smooth_rating,
+    monotonicity : monotonicity * weights.monotonicity,
+    snake : snake * weights.snake,
+    merges : merges * weights.merges
+  };
+  score
 }
 
 #[test]
@@ -273,129 +1211,414 @@ pub fn test_ExpectiMax_simple() {
                     0, 2, 0, 2,
                     0, 4, 4, 2,
                     0, 4, 2, 2);
-  let e = ExpectiMax::new(3, 5);
+  let mut e = ExpectiMax::new(3, 5, Heuristic::default());
   let _ = e.next_action(&board);
 }
 
+#[test]
+pub fn test_ExpectiMax_stats() {
+  let mut board = Board::empty();
+  board.vec  = vec!(0, 2, 4, 2,
+                    0, 2, 0, 2,
+                    0, 4, 4, 2,
+                    0, 4, 2, 2);
+  let mut e = ExpectiMax::new(2, 5, Heuristic::default());
+  let (_, stats) = e.search(&board);
+  let actions = board.get_actions().len();
+  assert_eq!(stats.searches, 1);
+  assert_eq!(stats.chance_nodes, actions);
+  assert_eq!(stats.expanded, 1 + actions);
+  //every max node below the root is at the depth limit
+  assert_eq!(stats.leaf_evals, stats.max_nodes - 1);
+  assert_eq!(stats.children, stats.nodes() - 1);
+  assert_eq!(stats.depth_reached, 2);
+}
+
+#[test]
+pub fn test_ExpectiMax_reuse() {
+  let mut board = Board::empty();
+  board.vec  = vec!(2, 4, 2, 4,
+                    4, 2, 4, 2,
+                    2, 4, 2, 4,
+                    0, 0, 0, 8);
+  //few enough empty spaces that every spawn of a 2 is sampled
+  let mut e = ExpectiMax::new(4, 5, Heuristic::default()).reusing(100000);
+  let (action, stats) = e.search(&board);
+  assert_eq!(stats.reuse_hits, 0);
+  let after = board.move(action);
+  let indx = *after.get_empty().get(0);
+  let next = after.add_space((indx, 2, 0.));
+  e.observe(action, (indx, 2), &next);
+  let (_, stats) = e.search(&next);
+  assert_eq!(stats.reuse_hits, 1);
+  assert!(stats.reused > 0);
+  //a position the tree does not lead to starts from scratch
+  let (_, stats) = e.search(&board);
+  assert_eq!(stats.reuse_hits, 0);
+  assert_eq!(stats.reused, 0);
+}
+
+#[test]
+pub fn test_ExpectiMax_no_samples() {
+  let mut board = Board::empty();
+  board.vec  = vec!(0, 2, 4, 2,
+                    0, 2, 0, 2,
+                    0, 4, 4, 2,
+                    0, 4, 2, 2);
+  let mut e = ExpectiMax::new(3, 0, Heuristic::default());
+  assert!(board.get_actions().contains(&e.next_action(&board)));
+}
+
+#[test]
+pub fn test_ExpectiMax_analyse() {
+  let mut board = Board::empty();
+  board.vec  = vec!(0, 2, 4, 2,
+                    0, 2, 0, 2,
+                    0, 4, 4, 2,
+                    0, 4, 2, 2);
+  let e = ExpectiMax::new(4, 5, Heuristic::default());
+  let analysis = e.analyse(&board);
+  assert_eq!(analysis.actions.len(), board.get_actions().len());
+  for pair in analysis.actions.as_slice().windows(2) {
+    assert!(pair[0].value >= pair[1].value);
+  }
+  let best = analysis.best();
+  assert!(analysis.margin() >= 0.);
+  assert!(best.score.is_some());
+  //move, spawn, move, spawn
+  assert_eq!(best.pv.len(), 4);
+  assert_eq!(best.pv.get(0).dir(), best.action);
+}
+
+#[test]
+pub fn test_ExpectiMax_target_tile() {
+  let mut board = Board::empty();
+  board.vec  = vec!(4, 4, 0, 0,
+                    0, 0, 0, 0,
+                    0, 0, 0, 0,
+                    0, 0, 0, 0);
+  let e = ExpectiMax::with_objective(2, 4, Heuristic::default(), TargetTile(8));
+  let analysis = e.analyse(&board);
+  //merging the 4s makes the target straight away, anything else has not yet
+  let best = analysis.best();
+  assert_eq!(best.value, 1.);
+  assert!(best.action == Left || best.action == Right);
+  for a in analysis.actions.iter().filter(|a| a.action == Up || a.action == Down) {
+    assert!(a.value < 1.);
+  }
+}
+
+#[test]
+pub fn test_ExpectiMax_target_already_reached() {
+  let mut board = Board::empty();
+  board.vec  = vec!(8, 4, 0, 0,
+                    2, 0, 0, 0,
+                    0, 0, 0, 0,
+                    0, 0, 0, 0);
+  //the target is already made, so the search plays on by the evaluation
+  let mut e = ExpectiMax::with_objective(2, 4, Heuristic::default(), TargetTile(8));
+  let (action, _) = e.search(&board);
+  assert!(board.get_actions().contains(&action));
+  let analysis = e.analyse(&board);
+  assert!(analysis.actions.iter().any(|a| a.value != 1.));
+}
+
+#[test]
+pub fn test_ExpectiMax_score_avoids_game_over() {
+  let mut board = Board::empty();
+  board.vec  = vec!(32,  4, 32, 16,
+                    16,  8, 16,  8,
+                     2, 32, 32, 32,
+                    32,  8, 16, 32);
+  //left merges the most but every spawn after it ends the game, up and down
+  //leave a full board that can still move
+  let mut e = ExpectiMax::with_objective(2, 32, Heuristic::default(), MaxScore);
+  let action = e.next_action(&board);
+  assert!(action == Up || action == Down, "{}", action);
+}
+
+#[test]
+pub fn test_herustic_symmetric() {
+  //the new terms should not care which corner the board is built in
+  let mut board = Board::empty();
+  board.vec  = vec!(64, 32, 16, 8,
+                    2,  4,  4,  2,
+                    0,  2,  0,  0,
+                    0,  0,  0,  0);
+  let mut flipped = Board::empty();
+  for y in range(0, 4) {
+    for x in range(0, 4) {
+      *flipped.get_mut(Cord(x, y).transform(3)) = board.get(Cord(x, y));
+    }
+  }
+  let a = herustic(&board, &Weights::default());
+  let b = herustic(&flipped, &Weights::default());
+  assert_eq!(a.monotonicity, b.monotonicity);
+  assert_eq!(a.snake, b.snake);
+  assert_eq!(a.merges, b.merges);
+  assert_eq!(a.merges, 1.);
+}
+
+
+#[test]
+pub fn test_FixedPriority_corner() {
+  let mut board = Board::empty();
+  board.vec  = vec!(2, 0, 0, 0,
+                    4, 0, 0, 0,
+                    8, 0, 0, 0,
+                    16, 0, 0, 0);
+  //Down and Left do nothing so the corner strategy has to go Right
+  assert_eq!(FixedPriority::corner().next_action(&board), Right);
+  board.vec  = vec!(0, 0, 0, 0,
+                    0, 0, 0, 0,
+                    0, 0, 0, 0,
+                    2, 4, 8, 16);
+  assert_eq!(FixedPriority::corner().next_action(&board), Up);
+}
+
+#[test]
+pub fn test_Cyclic_skips_illegal() {
+  let mut board = Board::empty();
+  board.vec  = vec!(2, 0, 0, 0,
+                    4, 0, 0, 0,
+                    8, 0, 0, 0,
+                    16, 0, 0, 0);
+  let mut c = Cyclic::new(vec!(Left, Down, Right, Up));
+  assert_eq!(c.next_action(&board), Right);
+  assert_eq!(c.next_action(&board), Up);
+  //a new game starts the pattern over
+  c.new_game();
+  assert_eq!(c.next_action(&board), Right);
+}
+
+#[test]
+pub fn test_Player_threads_do_not_change_reports() {
+  use spawn::SeededSpawn;
+  let play = |threads : uint| {
+    //few samples per chance node, so the search itself draws random numbers
+    let mut player = Player::with_pool(ExpectiMax::new(2, 3, Heuristic::default()), WorkerPool::new(threads));
+    player.seed = Some(7);
+    //fewer games than threads too
+    player.play(5, SeededSpawn::new(7));
+    player.reports.iter().map(|r| (r.score, r.moves)).collect::<Vec<(int, uint)>>()
+  };
+  let one = play(1);
+  assert_eq!(one.len(), 5);
+  assert_eq!(one, play(8));
+}
+
+#[test]
+#[should_fail]
+pub fn test_Player_bad_script_fails_instead_of_hanging() {
+  use spawn::ScriptedSpawn;
+  //the second spawn lands on the first
+  let mut player = Player::with_pool(GreedyScore::new(), WorkerPool::new(2));
+  player.play(1, ScriptedSpawn::new(vec!((0, 2), (0, 4))));
+}
 
 #[deriving(Show)]
 pub struct Report {
   pub moves : uint,
-  pub summary : Summary
+  pub score : int,
+  pub summary : Summary,
+  pub stats : SearchStats,
+  //Every position and move, only kept when asked for
+  pub record : Option<GameRecord>,
+  pub time_ns : u64 //Wall time for the whole game
 }
 
 impl Report {
-  pub fn new(moves : uint, summary : Summary) -> Report {
-    Report {moves : moves, summary : summary}
+  pub fn new(moves : uint, score : int, summary : Summary, stats : SearchStats,
+             record : Option<GameRecord>, time_ns : u64) -> Report {
+    Report {moves : moves, score : score, summary : summary, stats : stats, record : record,
+            time_ns : time_ns}
   }
 }
 
 #[deriving(Show)]
 pub struct Player<T> {
   pub player : T,
-  pub reports : Vec<Report>
+  pub reports : Vec<Report>,
+  //Tiles print_reports gives the reach rate of
+  pub thresholds : Vec<int>,
+  //Seed of the player's random choices, None for a different one every batch
+  pub seed : Option<u32>,
+  //Keep the GameRecord of every game in its report
+  pub record : bool,
+  pool : WorkerPool
 }
 
 impl<T : AIPlayer + Clone + Send> Player<T> {
+  //Plays one game per CPU at a time
   pub fn new(player : T) -> Player<T> {
-    Player { player : player , reports: vec!()}
+    Player::with_pool(player, WorkerPool::default())
   }
 
-  pub fn play_interactive(player : T) -> Report {
+  pub fn with_pool(player : T, pool : WorkerPool) -> Player<T> {
+    Player { player : player , reports: vec!(), thresholds : vec!(1024, 2048, 4096, 8192), seed : None,
+             record : false, pool : pool }
+  }
+
+  pub fn play_interactive<S : SpawnPolicy>(mut player : T, spawner : &mut S) -> Report {
     println!("starting");
-    let mut board = Board::new();
+    let start = time::precise_time_ns();
+    player.new_game();
+    let mut board = spawner.start();
     let mut moves = 0;
+    let mut score = 0;
+    let mut stats = SearchStats::new();
+    let mut record = GameRecord::new();
     while board.get_actions().len() > 0 {
-      let action = player.next_action(&board);
-      board = board.move(action).add_random();
-      let state = State::from_board(board.clone());
-      println!("{}", ExpectiMax::herustic(&state));
+      //the breakdown of the leaf the player is aiming for, from the same
+      //search that picks the move
+      let (action, move_stats) = match player.analysis(&board) {
+        Some(analysis) => {
+          let best = analysis.best();
+          match best.score {
+            Some(ref score) => println!("{}", score),
+            None => println!("{}", best.value)
+          }
+          (best.action, analysis.stats.clone())
+        }
+        None => player.search(&board)
+      };
+      stats.add(&move_stats);
+      record.push(&board, action);
+      let (after, reward) = board.move_and_score(action);
+      board = spawner.spawn(&after);
+      player.observe(action, spawned(&after, &board), &board);
+      score += reward;
       println!("{}", board);
       moves += 1;
     }
+    record.finish(&board);
     let sum = board.summary();
-    Report::new(moves, sum)
+    let report = Report::new(moves, score, sum, stats, Some(record), time::precise_time_ns() - start);
+    player.end_game(&report);
+    report
   }
 
-  pub fn play_one(player : T) -> Report {
+  pub fn play_one<S : SpawnPolicy>(mut player : T, spawner : &mut S, record : bool) -> Report {
     println!("starting");
-    let mut board = Board::new();
+    let (report, board) = Player::run_game(&mut player, spawner, record);
+    println!("{}", board);
+    report
+  }
+
+  //Plays a game without printing anything, returns the final board too.
+  //The report only has a GameRecord if record is set.
+  pub fn run_game<S : SpawnPolicy>(player : &mut T, spawner : &mut S, record : bool) -> (Report, Board) {
+    let start = time::precise_time_ns();
+    player.new_game();
+    let mut board = spawner.start();
     let mut moves = 0;
+    let mut score = 0;
+    let mut stats = SearchStats::new();
+    let mut record = if record { Some(GameRecord::new()) } else { None };
     while board.get_actions().len() > 0 {
-      let action = player.next_action(&board);
-      board = board.move(action).add_random();
+      let (action, move_stats) = player.search(&board);
+      stats.add(&move_stats);
+      for record in record.mut_iter() {
+        record.push(&board, action);
+      }
+      let (after, reward) = board.move_and_score(action);
+      board = spawner.spawn(&after);
+      player.observe(action, spawned(&after, &board), &board);
+      score += reward;
       moves += 1;
     }
-    println!("{}", board);
+    for record in record.mut_iter() {
+      record.finish(&board);
+    }
     let sum = board.summary();
-    Report::new(moves, sum)
+    let report = Report::new(moves, score, sum, stats, record, time::precise_time_ns() - start);
+    player.end_game(&report);
+    (report, board)
   }
 
-  pub fn launch(&self, tx : &Sender<Report>) {
-      let player = self.player.clone();
-      let tx = (*tx).clone();
-      spawn(proc() {
-        let report = Player::play_one(player.clone());
-        tx.send(report);
-      });
+  //Queues game number game on the pool. Every game gets its own copy of the
+  //player and of the spawner, reseeded for that game. Without a seed the
+  //copies still get different random numbers from each other. A game that
+  //fails, say on a bad spawn script, sends None instead of its report.
+  pub fn launch<S : SpawnPolicy + Clone + Send>(&self, game : uint, tx : &Sender<(uint, Option<Report>)>,
+                                                spawner : &S) {
+      let mut player = self.player.clone();
+      let seed = match self.seed {
+        Some(seed) => seed,
+        None => task_rng().gen()
+      };
+      player.reseed(seed, game);
+      let mut spawner = spawner.clone();
+      spawner.reseed(game);
+      let record = self.record;
+      self.pool.run(game, proc() {
+        let mut spawner = spawner;
+        Player::play_one(player, &mut spawner, record)
+      }, tx);
   }
 
-  pub fn play(&mut self, n : uint) {
+  //Plays n games, as many at once as the pool has threads. The reports are
+  //added in game order, so with a seeded spawner they do not depend on the
+  //number of threads.
+  pub fn play<S : SpawnPolicy + Clone + Send>(&mut self, n : uint, spawner : S) {
     let start = time::get_time();
-    let (tx, rx) : (Sender<_>, Receiver<_>) = channel();
-    let mut n_left = n-8;
-    for _ in range(0, 8) {
-      self.launch(&tx);
+    let (tx, rx) : (Sender<(uint, Option<Report>)>, Receiver<(uint, Option<Report>)>) = channel();
+    let first = self.reports.len();
+    let mut launched = 0;
+    while launched < n && launched < self.pool.threads() {
+      self.launch(launched, &tx, &spawner);
+      launched += 1;
     }
+    let mut order = vec!();
     for _ in range(0, n) {
-      self.reports.push(rx.recv());
-      if n_left != 0 {
-        self.launch(&tx);
-        n_left-=1;
+      let (game, report) = match rx.recv() {
+        (game, Some(report)) => (game, report),
+        (game, None) => fail!("game {} failed", game)
+      };
+      order.push(game);
+      self.reports.push(report);
+      if launched < n {
+        self.launch(launched, &tx, &spawner);
+        launched += 1;
       }
       if self.reports.len() % 5 == 0 {
         self.print_reports();
       }
     }
+    let mut reports = mem::replace(&mut self.reports, vec!()).move_iter();
+    self.reports = reports.by_ref().take(first).collect();
+    let mut played : Vec<(uint, Report)> = order.move_iter().zip(reports).collect();
+    played.sort_by(|&(a, _), &(b, _)| a.cmp(&b));
+    self.reports.extend(played.move_iter().map(|(_, report)| report));
     let end = time::get_time();
     let mut delta_s = (end.sec-start.sec) as f32;
     delta_s += (end.nsec-start.nsec) as f32 / 1e9;
     let gps = n as f32 / delta_s;
 
     let mut moves : i64 = 0;
+    let mut stats = SearchStats::new();
     for report in self.reports.iter() {
       moves += report.moves as i64;
+      stats.add(&report.stats);
     }
     println!("{} Games per second", gps);
     println!("{} Moves per second", moves as f32 / delta_s );
+    println!("{} Nodes per second", stats.nodes() as f32 / delta_s );
+    if stats.searches > 0 {
+      println!("{} Nodes per move, {} leaf evaluations per move, branching {}, depth {}, {} ms per move",
+               stats.nodes() / stats.searches, stats.leaf_evals / stats.searches,
+               stats.branching(), stats.depth_reached,
+               stats.time_ns as f32 / 1e6 / stats.searches as f32);
+      if stats.reuse_hits > 0 {
+        println!("{}% of searches reused the last tree, {} nodes per move not generated again",
+                 100. * stats.reuse_hits as f32 / stats.searches as f32,
+                 stats.reused as f32 / stats.searches as f32);
+      }
+    }
   }
 
   pub fn print_reports(&self) {
-    let mut p_1024 : int = 0;
-    let mut p_2048 : int = 0;
-    let mut p_4096 : int = 0;
-    let mut p_8192 : int = 0;
-
-    for report in self.reports.iter() {
-      if report.summary.best_tile >= 1024 {
-        p_1024 += 1;
-      }
-      if report.summary.best_tile >= 2048 {
-        p_2048 += 1;
-      }
-      if report.summary.best_tile >= 4096 {
-        p_4096 += 1;
-      }
-      if report.summary.best_tile >= 8192 {
-        p_8192 += 1;
-      }
-    }
-    println!("Scores from {} Samples\n==========", self.reports.len());
-    let l = self.reports.len() as f32;
-    println!("1024: {}", p_1024 as f32 / l);
-    println!("2048: {}", p_2048 as f32 / l);
-    println!("4096: {}", p_4096 as f32 / l);
-    println!("8192: {}", p_8192 as f32 / l);
+    BatchStats::new(&self.reports, &self.thresholds).print();
   }
 }
 
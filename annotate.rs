@@ -0,0 +1,87 @@
+extern crate rand;
+
+use std::os;
+use ai::{ExpectiMax, Heuristic};
+use record::GameRecord;
+use montecarlo::{MonteCarlo, EmptyRollout, RolloutSurvival};
+
+pub mod game;
+pub mod ai;
+pub mod minimax;
+pub mod spawn;
+pub mod record;
+pub mod pool;
+pub mod stats;
+pub mod montecarlo;
+
+//Playouts used to estimate the chance of surviving the next SURVIVAL_HORIZON moves
+static SURVIVAL_ROLLOUTS : uint = 200;
+static SURVIVAL_HORIZON : uint = 20;
+//A drop in that chance this large between two positions is a turning point
+static COLLAPSE : f32 = 0.25;
+
+fn option(args : &Vec<String>, flag : &str) -> Option<uint> {
+  let position = args.iter().position(|a| a.as_slice() == flag);
+  position.and_then(|i| if i + 1 < args.len() { from_str(args.get(i + 1).as_slice()) } else { None })
+}
+
+//Run the expectimax analysis over every position of a recorded game, from
+//batch --record or elsewhere, and point out the mistakes and the moments the
+//game was lost.
+//usage: annotate <record> [--last N] [--depth D]
+pub fn main() {
+  let args = os::args();
+  if args.len() < 2 {
+    println!("usage: annotate <record> [--last N] [--depth D]");
+    return
+  }
+  let record = GameRecord::load(&Path::new(args.get(1).as_slice())).unwrap();
+  if record.boards.len() == 0 {
+    println!("{} has no positions, it should be a game from batch --record", args.get(1));
+    println!("usage: annotate <record> [--last N] [--depth D]");
+    return
+  }
+  let depth = option(&args, "--depth").unwrap_or(4);
+  let moves = record.actions.len();
+  let first = match option(&args, "--last") {
+    Some(last) if last < moves => moves - last,
+    _ => 0
+  };
+
+  let expecti = ExpectiMax::new(depth, 8, Heuristic::default());
+  let survival = MonteCarlo::new(SURVIVAL_ROLLOUTS, Some(SURVIVAL_HORIZON), EmptyRollout,
+                                 RolloutSurvival, 4);
+  let mut total_loss = 0.;
+  let mut turning_points = vec!();
+  let mut last_survival : Option<f32> = None;
+  for i in range(first, moves) {
+    let board = record.boards.get(i);
+    let chosen = *record.actions.get(i);
+    let analysis = expecti.analyse(board);
+    let best = analysis.best();
+    let loss = match analysis.get(chosen) {
+      Some(a) => best.value - a.value,
+      None => 0.
+    };
+    let loss = if loss < 0. { 0. } else { loss };
+    total_loss += loss;
+
+    let alive = survival.action_values(board).iter().fold(0., |m, &(_, v)| if v > m { v } else { m });
+    let collapsed = match last_survival {
+      Some(previous) => previous - alive >= COLLAPSE,
+      None => false
+    };
+    last_survival = Some(alive);
+
+    println!("Move {}: played {}, best {}, loss {:.2}, survival {:.2}{}",
+             i + 1, chosen, best.action, loss, alive,
+             if collapsed { "  <- turning point" } else { "" });
+    if collapsed {
+      turning_points.push(i + 1);
+      println!("{}", board);
+    }
+  }
+  println!("Final position{}", record.boards.get(record.boards.len() - 1));
+  println!("{} moves annotated, total loss {:.2}, turning points at {}",
+           moves - first, total_loss, turning_points);
+}
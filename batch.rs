@@ -1,14 +1,308 @@
 extern crate rand;
 
-use ai::{Player, ExpectiMax};
+use std::os;
+use std::io::{fs, UserDir};
+use std::sync::Arc;
+use ai::{AIPlayer, Player, ExpectiMax, Heuristic, Weights, RandomPlayer, GreedyScore, Greedy};
+use ai::{SearchObjective, MaxEvaluation, MaxScore, TargetTile, MaxSurvival};
+use ai::{FixedPriority, Cyclic, EpsilonGreedy, seeded_rng};
+use game::{Down, Left, Right};
+use ntuple::{NTupleNetwork, NTuplePlayer};
+use nn::{Network, PolicyPlayer};
+use tables::TableHeuristic;
+use beam::BeamSearch;
+use ensemble::{Ensemble, MajorityVote, ValueAverage};
+use ensemble::{load_meta_policy, save_meta_policy, load_decisions, train_meta_policy};
+use montecarlo::{MonteCarlo, EmptyRollout, RolloutScore};
+use mcts::{Mcts, Evaluate};
+use minimax::Minimax;
+use exact::ExactExpectiMax;
+use spawn::{SpawnPolicy, RandomSpawn, SeededSpawn, AdversarialSpawn, HelpfulSpawn, ScriptedSpawn};
+use dataset::{DatasetWriter, Sample, self_play};
+use pool::WorkerPool;
 
 pub mod game;
 pub mod ai;
+pub mod ntuple;
+pub mod montecarlo;
+pub mod mcts;
+pub mod minimax;
+pub mod spawn;
+pub mod record;
+pub mod pool;
+pub mod stats;
+pub mod exact;
+pub mod solver;
+pub mod env;
+pub mod dataset;
+pub mod nn;
+pub mod tables;
+pub mod beam;
+pub mod ensemble;
+
+//Samples per dataset shard
+static SHARD_SIZE : uint = 65536;
+
+//Value following a flag such as --spawn
+fn option(args : &Vec<String>, flag : &str) -> Option<String> {
+  let position = args.iter().position(|a| a.as_slice() == flag);
+  position.and_then(|i| if i + 1 < args.len() { Some(args.get(i + 1).clone()) } else { None })
+}
+
+//--threads, or one per CPU
+fn pool(args : &Vec<String>) -> WorkerPool {
+  match option(args, "--threads") {
+    Some(n) => WorkerPool::new(from_str(n.as_slice()).expect("bad --threads")),
+    None => WorkerPool::default()
+  }
+}
+
+fn games(args : &Vec<String>) -> uint {
+  option(args, "--games").and_then(|g| from_str(g.as_slice())).unwrap_or(100)
+}
+
+fn run<T : AIPlayer + Clone + Send, S : SpawnPolicy + Clone + Send>(player : T, spawner : S,
+                                                                   args : &Vec<String>) {
+  let mut player = Player::with_pool(player, pool(args));
+  match option(args, "--thresholds") {
+    Some(list) => {
+      player.thresholds = list.as_slice().split(',').map(|t| from_str(t).expect("bad --thresholds")).collect()
+    }
+    None => ()
+  }
+  player.seed = option(args, "--seed").map(|seed| from_str(seed.as_slice()).expect("bad --seed"));
+  //games are only kept when they are going to be saved
+  let record_dir = option(args, "--record").map(|dir| Path::new(dir.as_slice()));
+  match record_dir {
+    Some(ref dir) => match fs::mkdir_recursive(dir, UserDir) {
+      Ok(()) => player.record = true,
+      Err(e) => {
+        println!("could not create {}: {}", dir.display(), e);
+        return
+      }
+    },
+    None => ()
+  }
+  player.play(games(args), spawner);
+  player.print_reports();
+  match record_dir {
+    Some(dir) => {
+      for (i, report) in player.reports.iter().enumerate() {
+        let path = dir.join(format!("game-{}.txt", i));
+        match report.record {
+          Some(ref record) => match record.save(&path) {
+            Ok(()) => (),
+            Err(e) => println!("could not save {}: {}", path.display(), e)
+          },
+          None => ()
+        }
+      }
+    }
+    None => ()
+  }
+}
+
+//The file named right after the player, as in batch ntuple <weights>
+fn file_arg(args : &Vec<String>, player : &str) -> Path {
+  if args.len() < 3 || args.get(2).as_slice().starts_with("--") {
+    fail!("batch {} needs <weights file>", player)
+  }
+  Path::new(args.get(2).as_slice())
+}
+
+//The weights from --weights, or the defaults
+fn weights(args : &Vec<String>) -> Weights {
+  match option(args, "--weights") {
+    Some(path) => Weights::load(&Path::new(path.as_slice())).unwrap(),
+    None => Weights::default()
+  }
+}
+
+fn heuristic(args : &Vec<String>) -> Heuristic {
+  Heuristic::new(weights(args))
+}
+
+//--objective evaluation | score | survival | <target tile>
+fn objective(args : &Vec<String>) -> SearchObjective {
+  match option(args, "--objective") {
+    None => MaxEvaluation,
+    Some(name) => match name.as_slice() {
+      "evaluation" => MaxEvaluation,
+      "score" => MaxScore,
+      "survival" => MaxSurvival,
+      tile => TargetTile(from_str(tile).expect("unknown objective"))
+    }
+  }
+}
+
+fn run_with_spawn<T : AIPlayer + Clone + Send>(player : T, args : &Vec<String>) {
+  let spawn = option(args, "--spawn").unwrap_or("random".to_owned());
+  match spawn.as_slice() {
+    "random" => match option(args, "--seed") {
+      Some(seed) => run(player, SeededSpawn::new(from_str(seed.as_slice()).expect("bad --seed")), args),
+      None => run(player, RandomSpawn, args)
+    },
+    "evil" => run(player, AdversarialSpawn::new(2, heuristic(args)), args),
+    "helpful" => run(player, HelpfulSpawn::new(2, heuristic(args)), args),
+    "script" => {
+      let path = option(args, "--script").expect("--spawn script needs --script <file>");
+      run(player, ScriptedSpawn::load(&Path::new(path.as_slice())).unwrap(), args)
+    }
+    _ => println!("unknown spawn policy {}", spawn)
+  }
+}
+
+fn launch_self_play(pool : &WorkerPool, expecti : &ExpectiMax<TableHeuristic>, game : uint, seed : uint,
+                    tx : &Sender<(uint, Option<Vec<Sample>>)>) {
+  let expecti = expecti.clone();
+  pool.run(game, proc() {
+    self_play(&expecti, &mut SeededSpawn::new(seed as u32), &mut seeded_rng(seed as u32, 0))
+  }, tx);
+}
+
+//ExpectiMax self-play, every position with its action values goes to the
+//dataset. Game i uses seed --seed + i for its spawns and the search's samples,
+//and games are written in order whichever finishes first, so a dataset can be
+//regenerated exactly and another one made from games it does not share.
+fn self_play_dataset(args : &Vec<String>) {
+  let dir = option(args, "--dataset").expect("selfplay needs --dataset <dir>");
+  let games = games(args);
+  let first_seed : uint = option(args, "--seed").map_or(0, |seed| from_str(seed.as_slice()).expect("bad --seed"));
+  let pool = pool(args);
+  let dedupe = args.iter().any(|a| a.as_slice() == "--dedupe");
+  let mut writer = DatasetWriter::new(&Path::new(dir.as_slice()), SHARD_SIZE, dedupe).unwrap();
+  let expecti = ExpectiMax::new(6, 6, TableHeuristic::new(weights(args)));
+  let (tx, rx) : (Sender<(uint, Option<Vec<Sample>>)>, Receiver<(uint, Option<Vec<Sample>>)>) = channel();
+  let mut launched = 0;
+  while launched < games && launched < pool.threads() {
+    launch_self_play(&pool, &expecti, launched, first_seed + launched, &tx);
+    launched += 1;
+  }
+  //games that finished before one launched earlier wait here for their turn
+  let mut finished : Vec<Option<Vec<Sample>>> = Vec::from_fn(games, |_| None);
+  let mut written = 0;
+  for _ in range(0, games) {
+    match rx.recv() {
+      (game, Some(samples)) => *finished.get_mut(game) = Some(samples),
+      (game, None) => fail!("self-play game {} failed", game)
+    }
+    while written < games && finished.get(written).is_some() {
+      let samples = finished.get_mut(written).take().unwrap();
+      writer.add(samples).unwrap();
+      written += 1;
+      if written % 10 == 0 || written == games {
+        println!("{} games, {} positions written, {} duplicates skipped", written,
+                 writer.written, writer.skipped);
+      }
+    }
+    if launched < games {
+      launch_self_play(&pool, &expecti, launched, first_seed + launched, &tx);
+      launched += 1;
+    }
+  }
+  writer.flush().unwrap();
+  println!("{} positions written, {} duplicates skipped", writer.written, writer.skipped);
+}
+
+//Expectimax at two depths, one with the row tables and the --weights, plus
+//mcts and monte carlo, combined with --combine vote | average | meta --meta <file>
+fn ensemble(args : &Vec<String>) {
+  let mut ensemble = Ensemble::new(MajorityVote);
+  ensemble.push("expectimax-2", ExpectiMax::new(2, 6, Heuristic::default()));
+  ensemble.push("expectimax-4", ExpectiMax::new(4, 6, TableHeuristic::new(weights(args))));
+  ensemble.push("mcts", Mcts::new(500, 1.4, Evaluate(Heuristic::default())));
+  ensemble.push("montecarlo", MonteCarlo::new(50, Some(20), EmptyRollout, RolloutScore, 1));
+  ensemble.set_combination(match option(args, "--combine").unwrap_or("vote".to_owned()).as_slice() {
+    "average" => ValueAverage,
+    "meta" => {
+      let path = option(args, "--meta").expect("--combine meta needs --meta <file>");
+      load_meta_policy(&Path::new(path.as_slice()), ensemble.len()).unwrap()
+    }
+    _ => MajorityVote
+  });
+  run_with_spawn(ensemble.clone(), args);
+  for (name, wins) in ensemble.wins().move_iter() {
+    println!("{} agreed with {} decisions", name, wins);
+  }
+  match option(args, "--decisions") {
+    Some(path) => match ensemble.save_decisions(&Path::new(path.as_slice())) {
+      Ok(()) => (),
+      Err(e) => println!("could not save decisions to {}: {}", path, e)
+    },
+    None => ()
+  }
+}
+
+//Fits meta-policy weights to the games logged by ensemble --decisions
+fn train_meta(args : &Vec<String>) {
+  let decisions = option(args, "--decisions").expect("train-meta needs --decisions <file>");
+  let out = option(args, "--meta").expect("train-meta needs --meta <file to write>");
+  let epochs = option(args, "--epochs").map_or(200, |n| from_str(n.as_slice()).expect("bad --epochs"));
+  let logs = load_decisions(&Path::new(decisions.as_slice())).unwrap();
+  let weights = train_meta_policy(&logs, epochs, 0.5);
+  println!("meta-policy weights from {} games: {}", logs.len(), weights);
+  save_meta_policy(&weights, &Path::new(out.as_slice())).unwrap();
+}
 
 //Do a batch run and get statistics back to test AI
+//usage: batch [expectimax | exact | montecarlo | mcts | minimax | beam | ntuple <weights> |
+//              ntuple-greedy <weights> | nn <weights> | nn-policy <weights> | ensemble | random |
+//              greedy-score | greedy-herustic | corner | cyclic | epsilon-corner]
+//             [--spawn random | evil | helpful | script --script <file>]
+//             [--weights <herustic weights file>] [--record <dir to save games in>]
+//             [--objective evaluation | score | survival | <target tile>]
+//             [--reuse <search tree nodes kept between moves>]
+//             [--games n] [--threads n] [--seed <seed for random spawns and the player>]
+//             [--thresholds <tiles to give reach rates of, such as 512,1024,2048>]
+//       batch ensemble [--combine vote | average | meta --meta <weights file>]
+//                      [--decisions <file to log every decision of every game in>]
+//       batch train-meta --decisions <file> --meta <weights file to write> [--epochs n]
+//       batch selfplay --dataset <dir> [--games n] [--seed <seed of the first game>] [--threads n]
+//                      [--dedupe] [--weights <file>]
 pub fn main() {
-  let expecti = ExpectiMax::new(6, 6);
-  let mut player = Player::new(expecti);
-  player.play(100);
-  player.print_reports();
+  let args = os::args();
+  let name = if args.len() > 1 { args.get(1).as_slice() } else { "expectimax" };
+  let h = heuristic(&args);
+  match name {
+    "expectimax" => {
+      let evaluator = TableHeuristic::new(weights(&args));
+      let expecti = ExpectiMax::with_objective(6, 6, evaluator, objective(&args));
+      match option(&args, "--reuse") {
+        Some(n) => run_with_spawn(expecti.reusing(from_str(n.as_slice()).expect("bad --reuse")), &args),
+        None => run_with_spawn(expecti, &args)
+      }
+    }
+    "exact" => run_with_spawn(ExactExpectiMax::new(4, h.clone()), &args),
+    "montecarlo" => run_with_spawn(MonteCarlo::new(100, None, EmptyRollout, RolloutScore, 1), &args),
+    "mcts" => run_with_spawn(Mcts::new(2000, 1.4, Evaluate(h.clone())), &args),
+    "minimax" => run_with_spawn(Minimax::new(4, h.clone()), &args),
+    "beam" => run_with_spawn(BeamSearch::new(16, 6, TableHeuristic::new(weights(&args))), &args),
+    //the network values the score still to come, so the search adds it to the merges on the way
+    "ntuple" => {
+      let network = NTupleNetwork::load(&file_arg(&args, name)).unwrap();
+      run_with_spawn(ExpectiMax::with_objective(2, 6, Arc::new(network), MaxScore), &args)
+    }
+    "ntuple-greedy" => {
+      let network = NTupleNetwork::load(&file_arg(&args, name)).unwrap();
+      run_with_spawn(NTuplePlayer::new(Arc::new(network)), &args)
+    }
+    "nn" => {
+      let network = Network::load(&file_arg(&args, name)).unwrap();
+      run_with_spawn(ExpectiMax::new(2, 6, Arc::new(network)), &args)
+    }
+    "nn-policy" => {
+      let network = Network::load(&file_arg(&args, name)).unwrap();
+      run_with_spawn(PolicyPlayer::new(Arc::new(network)), &args)
+    }
+    "random" => run_with_spawn(RandomPlayer::new(), &args),
+    "greedy-score" => run_with_spawn(GreedyScore::new(), &args),
+    "greedy-herustic" => run_with_spawn(Greedy::new(h.clone()), &args),
+    "corner" => run_with_spawn(FixedPriority::corner(), &args),
+    "cyclic" => run_with_spawn(Cyclic::new(vec!(Down, Left, Down, Right)), &args),
+    "epsilon-corner" => run_with_spawn(EpsilonGreedy::new(FixedPriority::corner(), 0.1), &args),
+    "ensemble" => ensemble(&args),
+    "selfplay" => self_play_dataset(&args),
+    "train-meta" => train_meta(&args),
+    _ => println!("unknown player {}", name)
+  }
 }
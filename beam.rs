@@ -0,0 +1,104 @@
+use std::cmp::{Less, Greater, Equal};
+use rand::XorShiftRng;
+
+use game::{Action, Board};
+use ai::{AIPlayer, Evaluator, seeded_rng, random_rng};
+
+//Beam search: every ply each board in the beam tries all its moves with one
+//sampled spawn after each, and only the width best boards by the evaluator
+//are kept. Much cheaper than ExpectiMax when boards are large.
+#[deriving(Clone)]
+pub struct BeamSearch<E> {
+  width : uint,
+  depth : uint,
+  evaluator : E,
+  rng : XorShiftRng //Samples the spawns
+}
+
+impl<E : Evaluator> BeamSearch<E> {
+  pub fn new(width : uint, depth : uint, evaluator : E) -> BeamSearch<E> {
+    BeamSearch {
+      width : if width == 0 { 1 } else { width },
+      depth : if depth == 0 { 1 } else { depth },
+      evaluator : evaluator,
+      rng : random_rng()
+    }
+  }
+
+  //The width best children of beam, each with the first action that led to it
+  fn expand(&mut self, beam : &Vec<(Option<Action>, Board)>) -> Vec<(Action, Board, f32)> {
+    let mut children = vec!();
+    for &(first, ref board) in beam.iter() {
+      for &action in board.get_actions().iter() {
+        let next = board.move(action).add_random_with(&mut self.rng);
+        let value = self.evaluator.evaluate(&next);
+        children.push((first.unwrap_or(action), next, value));
+      }
+    }
+    children.sort_by(|&(_, _, a), &(_, _, b)| if a > b { Less } else if a < b { Greater } else { Equal });
+    children.truncate(self.width);
+    children
+  }
+}
+
+impl<E : Evaluator> AIPlayer for BeamSearch<E> {
+  fn reseed(&mut self, seed : u32, game : uint) {
+    self.rng = seeded_rng(seed, game);
+  }
+
+  fn next_action(&mut self, board : &Board) -> Action {
+    let mut beam = vec!((None, board.clone()));
+    let mut best = None;
+    for _ in range(0, self.depth) {
+      let children = self.expand(&beam);
+      //every line in the beam has died, keep the last best
+      if children.len() == 0 {
+        break
+      }
+      let &(first, _, _) = children.get(0);
+      best = Some(first);
+      beam = children.move_iter().map(|(first, board, _)| (Some(first), board)).collect();
+    }
+    best.unwrap()
+  }
+}
+
+#[test]
+pub fn test_BeamSearch_legal() {
+  use ai::Heuristic;
+  use game::{Left, Right};
+  let mut board = Board::empty();
+  board.vec  = vec!(0, 2, 4, 2,
+                    0, 2, 0, 2,
+                    0, 4, 4, 2,
+                    0, 4, 2, 2);
+  let mut beam = BeamSearch::new(8, 3, Heuristic::default());
+  assert!(board.get_actions().contains(&beam.next_action(&board)));
+
+  //down leaves a full board, left and right merge the 256s
+  board.vec  = vec!(2,   4,   8,  16,
+                    4,   8,  16,  32,
+                    8,  16,  32,  64,
+                    0, 128, 256, 256);
+  //the sampled spawns are fixed so the test does not depend on the task's luck
+  beam.reseed(1, 0);
+  let action = beam.next_action(&board);
+  assert!(action == Left || action == Right, "{}", action);
+}
+
+#[test]
+pub fn test_BeamSearch_other_sizes() {
+  use ai::Heuristic;
+  let mut beam = BeamSearch::new(4, 2, Heuristic::default());
+  for &(width, height) in [(3, 3), (5, 5), (2, 4)].iter() {
+    let mut board = Board::new_sized(width, height);
+    for _ in range(0u, 20) {
+      if board.get_actions().len() == 0 {
+        break
+      }
+      let action = beam.next_action(&board);
+      assert!(board.get_actions().contains(&action));
+      board = board.move(action).add_random();
+    }
+  }
+}
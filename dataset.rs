@@ -0,0 +1,275 @@
+use std::collections::HashSet;
+use std::io::{File, IoResult, IoError, BufferedReader, BufferedWriter, InvalidInput, UserDir};
+use std::io::fs;
+use std::num::{Float, pow};
+use rand::XorShiftRng;
+
+use game::{Action, Board, Cord, exponent};
+use ai::{ExpectiMax, Evaluator};
+use spawn::SpawnPolicy;
+use env::{ACTIONS, action_index};
+
+static FILE_VERSION : u32 = 1;
+
+//One position from self-play
+#[deriving(Clone, Show)]
+pub struct Sample {
+  pub board : Board,
+  //Search value of each of env::ACTIONS, NaN where the action is illegal
+  pub values : [f32, ..4],
+  pub action : Action,
+  //Moves played before this position
+  pub moves : uint,
+  //How the game ended
+  pub score : int,
+  pub best_tile : int
+}
+
+//Play one game with expecti, keeping its analysis of every position. The
+//search samples spawns from rng, so a seeded spawner and rng replay a game.
+pub fn self_play<E : Evaluator, S : SpawnPolicy>(expecti : &ExpectiMax<E>, spawner : &mut S,
+                                                  rng : &mut XorShiftRng) -> Vec<Sample> {
+  let mut board = spawner.start();
+  let mut score = 0;
+  let mut samples = vec!();
+  while board.get_actions().len() > 0 {
+    let analysis = expecti.analyse_with(&board, rng);
+    let nan : f32 = Float::nan();
+    let mut values = [nan, ..4];
+    for a in analysis.actions.iter() {
+      values[action_index(a.action)] = a.value;
+    }
+    let action = analysis.best().action;
+    samples.push(Sample {
+      board : board.clone(),
+      values : values,
+      action : action,
+      moves : samples.len(),
+      score : 0,
+      best_tile : 0
+    });
+    let (after, reward) = board.move_and_score(action);
+    score += reward;
+    board = spawner.spawn(&after);
+  }
+  for sample in samples.mut_iter() {
+    sample.score = score;
+    sample.best_tile = board.get_best_tile();
+  }
+  samples
+}
+
+//Smallest of the 8 symmetric forms of a 4x4 board, other sizes are left as they are
+pub fn canonical(board : &Board) -> Vec<int> {
+  if board.width != 4 || board.height != 4 {
+    return board.vec.clone()
+  }
+  range(0u, 8).map(|sym| {
+    Vec::from_fn(16, |i| board.get(Cord((i % 4) as int, (i / 4) as int).transform(sym)))
+  }).min().unwrap()
+}
+
+fn bad_file(desc : &'static str) -> IoError {
+  IoError { kind : InvalidInput, desc : desc, detail : None }
+}
+
+//Writes samples into numbered shards of a directory along with an index.
+//
+//Shard format, all little endian:
+//  "SPDS", version u32, width u8, height u8, sample count u32
+//  for each sample: one u8 tile exponent per cell in the order of Board::vec,
+//  4 f32 action values, u8 index of the action played, u32 moves,
+//  u32 final score, u8 exponent of the best tile
+//
+//index.txt has one "file samples" line per shard.
+pub struct DatasetWriter {
+  dir : Path,
+  shard_size : uint,
+  //Canonical forms already written, when deduplicating
+  seen : Option<HashSet<Vec<int>>>,
+  pending : Vec<Sample>,
+  shards : Vec<(String, uint)>,
+  pub written : uint,
+  pub skipped : uint
+}
+
+impl DatasetWriter {
+  pub fn new(dir : &Path, shard_size : uint, dedupe : bool) -> IoResult<DatasetWriter> {
+    try!(fs::mkdir_recursive(dir, UserDir));
+    Ok(DatasetWriter {
+      dir : dir.clone(),
+      shard_size : shard_size,
+      seen : if dedupe { Some(HashSet::new()) } else { None },
+      pending : vec!(),
+      shards : vec!(),
+      written : 0,
+      skipped : 0
+    })
+  }
+
+  pub fn add(&mut self, samples : Vec<Sample>) -> IoResult<()> {
+    for sample in samples.move_iter() {
+      let fresh = match self.seen {
+        Some(ref mut seen) => seen.insert(canonical(&sample.board)),
+        None => true
+      };
+      if !fresh {
+        self.skipped += 1;
+        continue
+      }
+      self.pending.push(sample);
+      if self.pending.len() == self.shard_size {
+        try!(self.flush());
+      }
+    }
+    Ok(())
+  }
+
+  //Write out whatever is pending as a shard and update the index
+  pub fn flush(&mut self) -> IoResult<()> {
+    if self.pending.len() == 0 {
+      return Ok(())
+    }
+    let name = format!("shard-{:04}.bin", self.shards.len());
+    try!(write_shard(&self.dir.join(name.as_slice()), &self.pending));
+    self.written += self.pending.len();
+    self.shards.push((name, self.pending.len()));
+    self.pending.clear();
+    let mut index = try!(File::create(&self.dir.join("index.txt")));
+    for &(ref name, count) in self.shards.iter() {
+      try!(writeln!(index, "{} {}", name, count));
+    }
+    Ok(())
+  }
+}
+
+pub fn write_shard(path : &Path, samples : &Vec<Sample>) -> IoResult<()> {
+  let mut w = BufferedWriter::new(try!(File::create(path)));
+  let (width, height) = match samples.iter().next() {
+    Some(sample) => (sample.board.width, sample.board.height),
+    None => (4, 4)
+  };
+  try!(w.write(b"SPDS"));
+  try!(w.write_le_u32(FILE_VERSION));
+  try!(w.write_u8(width as u8));
+  try!(w.write_u8(height as u8));
+  try!(w.write_le_u32(samples.len() as u32));
+  for sample in samples.iter() {
+    for &tile in sample.board.vec.iter() {
+      try!(w.write_u8(exponent(tile) as u8));
+    }
+    for &value in sample.values.iter() {
+      try!(w.write_le_f32(value));
+    }
+    try!(w.write_u8(action_index(sample.action) as u8));
+    try!(w.write_le_u32(sample.moves as u32));
+    try!(w.write_le_u32(sample.score as u32));
+    try!(w.write_u8(exponent(sample.best_tile) as u8));
+  }
+  w.flush()
+}
+
+fn tile(exponent : u8) -> int {
+  if exponent == 0 { 0 } else { pow(2, exponent as uint) }
+}
+
+pub fn read_shard(path : &Path) -> IoResult<Vec<Sample>> {
+  let mut r = BufferedReader::new(try!(File::open(path)));
+  let magic = try!(r.read_exact(4));
+  if magic.as_slice() != b"SPDS" {
+    return Err(bad_file("not a self-play shard"))
+  }
+  if try!(r.read_le_u32()) != FILE_VERSION {
+    return Err(bad_file("unknown shard version"))
+  }
+  let width = try!(r.read_u8()) as int;
+  let height = try!(r.read_u8()) as int;
+  let count = try!(r.read_le_u32()) as uint;
+  let mut samples = vec!();
+  for _ in range(0, count) {
+    let mut board = Board::empty_sized(width, height);
+    for cell in board.vec.mut_iter() {
+      *cell = tile(try!(r.read_u8()));
+    }
+    let mut values = [0f32, ..4];
+    for value in values.mut_iter() {
+      *value = try!(r.read_le_f32());
+    }
+    let action = try!(r.read_u8()) as uint;
+    if action >= 4 {
+      return Err(bad_file("bad action in shard"))
+    }
+    let moves = try!(r.read_le_u32()) as uint;
+    let score = try!(r.read_le_u32()) as int;
+    let best_tile = tile(try!(r.read_u8()));
+    samples.push(Sample {
+      board : board,
+      values : values,
+      action : ACTIONS[action],
+      moves : moves,
+      score : score,
+      best_tile : best_tile
+    });
+  }
+  Ok(samples)
+}
+
+//Shard files and their sample counts, from index.txt
+pub fn read_index(dir : &Path) -> IoResult<Vec<(Path, uint)>> {
+  let mut reader = BufferedReader::new(try!(File::open(&dir.join("index.txt"))));
+  let mut shards = vec!();
+  for line in reader.lines() {
+    let line = try!(line);
+    let words : Vec<&str> = line.as_slice().words().collect();
+    if words.len() != 2 {
+      continue
+    }
+    match from_str::<uint>(*words.get(1)) {
+      Some(count) => shards.push((dir.join(*words.get(0)), count)),
+      None => return Err(bad_file("bad index line"))
+    }
+  }
+  Ok(shards)
+}
+
+#[test]
+pub fn test_dataset_round_trip() {
+  use ai::{Heuristic, seeded_rng};
+  use spawn::SeededSpawn;
+  let expecti = ExpectiMax::new(1, 2, Heuristic::default());
+  let samples = self_play(&expecti, &mut SeededSpawn::new(1), &mut seeded_rng(1, 0));
+  assert!(samples.len() > 0);
+  let dir = Path::new("test_dataset");
+  let mut writer = DatasetWriter::new(&dir, 100, true).unwrap();
+  writer.add(samples.clone()).unwrap();
+  //the same game again is all duplicates
+  writer.add(samples.clone()).unwrap();
+  writer.flush().unwrap();
+  assert_eq!(writer.written, samples.len());
+  assert_eq!(writer.skipped, samples.len());
+
+  let mut loaded = vec!();
+  for &(ref path, count) in read_index(&dir).unwrap().iter() {
+    let shard = read_shard(path).unwrap();
+    assert_eq!(shard.len(), count);
+    loaded.push_all_move(shard);
+  }
+  fs::rmdir_recursive(&dir).unwrap();
+  assert_eq!(loaded.len(), writer.written);
+  let first = loaded.get(0);
+  assert_eq!(first.board, samples.get(0).board);
+  assert_eq!(first.action, samples.get(0).action);
+  assert_eq!(first.score, samples.get(0).score);
+  assert!(!first.values[action_index(first.action)].is_nan());
+
+  //tiles past 32768 need more than 4 bits of exponent
+  let mut big = samples.get(0).clone();
+  *big.board.vec.get_mut(0) = 131072;
+  big.best_tile = 131072;
+  let path = Path::new("test_big_tile.spds");
+  write_shard(&path, &vec!(big.clone())).unwrap();
+  let shard = read_shard(&path).unwrap();
+  fs::unlink(&path).unwrap();
+  assert_eq!(shard.get(0).board, big.board);
+  assert_eq!(shard.get(0).best_tile, 131072);
+}
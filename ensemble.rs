@@ -0,0 +1,474 @@
+use std::io::{BufferedReader, File, IoResult, IoError, InvalidInput};
+use std::iter::AdditiveIterator;
+use std::mem;
+use std::num::Float;
+use std::sync::{Arc, Mutex};
+
+use game::{Action, Board};
+use ai::{AIPlayer, Report};
+use record::{letter, from_letter};
+use env::{ACTIONS, action_index};
+
+//A player that can be kept in an Ensemble
+pub trait Member : AIPlayer + Send {
+  fn clone_member(&self) -> Box<Member + Send>;
+}
+
+impl<T : AIPlayer + Clone + Send> Member for T {
+  fn clone_member(&self) -> Box<Member + Send> {
+    box self.clone() as Box<Member + Send>
+  }
+}
+
+//How the members' recommendations become one move
+#[deriving(Clone, Show)]
+pub enum Combination {
+  //Most members wins, ties go to the earliest member
+  MajorityVote,
+  //Each member's action scores are scaled to 0..1 and averaged
+  ValueAverage,
+  //Like ValueAverage with a weight per member, learned from logged games by
+  //train_meta_policy
+  MetaPolicy(Vec<f32>)
+}
+
+fn bad_line(desc : &'static str, line : &str) -> IoError {
+  IoError { kind : InvalidInput, desc : desc, detail : Some(line.to_owned()) }
+}
+
+//One weight per line in the order members were added, exactly one for each
+//of the members. Blank lines and lines starting with # are skipped.
+pub fn load_meta_policy(path : &Path, members : uint) -> IoResult<Combination> {
+  let mut reader = BufferedReader::new(try!(File::open(path)));
+  let mut weights = vec!();
+  for line in reader.lines() {
+    let line = try!(line);
+    let word = line.as_slice().trim();
+    if word.len() == 0 || word.starts_with("#") {
+      continue
+    }
+    match from_str::<f32>(word) {
+      Some(w) => weights.push(w),
+      None => return Err(bad_line("bad meta-policy weight", word))
+    }
+  }
+  if weights.len() != members {
+    return Err(IoError { kind : InvalidInput, desc : "meta-policy weights do not match the members",
+                         detail : Some(format!("{} weights for {} members", weights.len(), members)) })
+  }
+  Ok(MetaPolicy(weights))
+}
+
+pub fn save_meta_policy(weights : &Vec<f32>, path : &Path) -> IoResult<()> {
+  let mut file = try!(File::create(path));
+  for weight in weights.iter() {
+    try!(writeln!(file, "{}", weight));
+  }
+  Ok(())
+}
+
+//Learns meta-policy weights from logged games with REINFORCE. The policy is
+//a softmax over the legal actions of the weighted member scores, whose best
+//action is the one MetaPolicy plays. Each epoch makes the moves of games that
+//scored above the mean more likely and the moves of the rest less.
+pub fn train_meta_policy(logs : &Vec<GameLog>, epochs : uint, rate : f32) -> Vec<f32> {
+  let members = match logs.iter().flat_map(|log| log.decisions.iter()).next() {
+    Some(decision) => decision.picks.len(),
+    None => return vec!()
+  };
+  let n = logs.len() as f32;
+  let mean = logs.iter().fold(0., |t, log| t + log.score as f32) / n;
+  let var = logs.iter().fold(0., |t, log| t + (log.score as f32 - mean) * (log.score as f32 - mean)) / n;
+  let sd = if var > 0. { var.sqrt() } else { 1. };
+  let inf : f32 = Float::infinity();
+  let mut weights = Vec::from_elem(members, 1f32);
+  for _ in range(0, epochs) {
+    let mut grad = Vec::from_elem(members, 0f32);
+    let mut count = 0u;
+    for log in logs.iter() {
+      let advantage = (log.score as f32 - mean) / sd;
+      for d in log.decisions.iter() {
+        let legal : Vec<uint> = d.board.get_actions().iter().map(|&a| action_index(a)).collect();
+        let logits : Vec<f32> = legal.iter().map(|&i| {
+          d.scores.iter().zip(weights.iter()).fold(0., |t, (s, &w)| t + w * s[i])
+        }).collect();
+        let top = logits.iter().fold(-inf, |m, &l| m.max(l));
+        let exps : Vec<f32> = logits.iter().map(|&l| (l - top).exp()).collect();
+        let z = exps.iter().fold(0., |t, &e| t + e);
+        let played = action_index(d.action);
+        for (m, s) in d.scores.iter().enumerate() {
+          let expected = legal.iter().zip(exps.iter()).fold(0., |t, (&i, &e)| t + e / z * s[i]);
+          *grad.get_mut(m) += advantage * (s[played] - expected);
+        }
+        count += 1;
+      }
+    }
+    if count == 0 {
+      break
+    }
+    for (w, g) in weights.mut_iter().zip(grad.iter()) {
+      *w += rate * *g / count as f32;
+    }
+  }
+  weights
+}
+
+//Scores of the legal actions scaled so the worst is 0 and the best 1, indexed
+//like env::ACTIONS. Members without scores give 1 to their move only.
+fn scaled_scores(member : &mut Box<Member + Send>, board : &Board) -> (Action, [f32, ..4]) {
+  let mut scaled = [0f32, ..4];
+  match member.action_scores(board) {
+    Some(ref scores) if scores.len() > 0 => {
+      let (mut lo, mut hi) = (scores.get(0).val1(), scores.get(0).val1());
+      let mut best = scores.get(0).val0();
+      for &(action, score) in scores.iter() {
+        if score > hi {
+          hi = score;
+          best = action;
+        }
+        if score < lo {
+          lo = score;
+        }
+      }
+      for &(action, score) in scores.iter() {
+        scaled[action_index(action)] = if hi > lo { (score - lo) / (hi - lo) } else { 1. };
+      }
+      (best, scaled)
+    }
+    _ => {
+      let action = member.next_action(board);
+      scaled[action_index(action)] = 1.;
+      (action, scaled)
+    }
+  }
+}
+
+//What every member made of one position and the action played
+#[deriving(Clone, Show)]
+pub struct Decision {
+  pub board : Board,
+  pub picks : Vec<Action>,
+  //What each member's recommendation added to each action before weighting,
+  //indexed like env::ACTIONS
+  pub scores : Vec<[f32, ..4]>,
+  pub action : Action
+}
+
+//The decisions of one game in move order, and how it ended
+#[deriving(Clone, Show)]
+pub struct GameLog {
+  pub game : uint,
+  pub score : int,
+  pub decisions : Vec<Decision>
+}
+
+//Several players deciding together. Each copy keeps the decisions of the game
+//it is playing and adds them to a log shared by all clones when the game
+//ends, so the log covers every game of a batch.
+pub struct Ensemble {
+  names : Vec<String>,
+  members : Vec<Box<Member + Send>>,
+  combination : Combination,
+  //Number of the game being played in its batch, from reseed
+  game : uint,
+  pending : Vec<Decision>,
+  logs : Arc<Mutex<Vec<GameLog>>>
+}
+
+impl Clone for Ensemble {
+  fn clone(&self) -> Ensemble {
+    Ensemble {
+      names : self.names.clone(),
+      members : self.members.iter().map(|m| m.clone_member()).collect(),
+      combination : self.combination.clone(),
+      game : self.game,
+      pending : self.pending.clone(),
+      logs : self.logs.clone()
+    }
+  }
+}
+
+impl Ensemble {
+  pub fn new(combination : Combination) -> Ensemble {
+    Ensemble { names : vec!(), members : vec!(), combination : combination, game : 0,
+               pending : vec!(), logs : Arc::new(Mutex::new(vec!())) }
+  }
+
+  pub fn push<T : AIPlayer + Clone + Send>(&mut self, name : &str, player : T) {
+    self.names.push(name.to_owned());
+    self.members.push(box player as Box<Member + Send>);
+  }
+
+  pub fn len(&self) -> uint {
+    self.members.len()
+  }
+
+  pub fn set_combination(&mut self, combination : Combination) {
+    self.combination = combination;
+  }
+
+  //Every finished game so far, in game order
+  pub fn logs(&self) -> Vec<GameLog> {
+    let mut logs = self.logs.lock().clone();
+    logs.sort_by(|a, b| a.game.cmp(&b.game));
+    logs
+  }
+
+  //How many decisions of finished games each member agreed with
+  pub fn wins(&self) -> Vec<(String, uint)> {
+    let logs = self.logs.lock();
+    self.names.iter().enumerate().map(|(m, name)| {
+      (name.clone(), logs.iter().map(|log| {
+        log.decisions.iter().map(|d| if *d.picks.get(m) == d.action { 1 } else { 0 }).sum()
+      }).sum())
+    }).collect()
+  }
+
+  //One line per decision, in game order then move order:
+  //  game, final score, width, height, the tiles in the order of Board::vec,
+  //  the action played, member count, each member's pick, then each member's
+  //  four scores in the order of env::ACTIONS
+  //Actions are wasd letters.
+  pub fn save_decisions(&self, path : &Path) -> IoResult<()> {
+    let mut file = try!(File::create(path));
+    for log in self.logs().iter() {
+      for d in log.decisions.iter() {
+        let tiles : Vec<String> = d.board.vec.iter().map(|t| t.to_str()).collect();
+        let picks : Vec<&str> = d.picks.iter().map(|&a| letter(a)).collect();
+        let mut scores = vec!();
+        for member in d.scores.iter() {
+          scores.extend(member.iter().map(|s| s.to_str()));
+        }
+        try!(writeln!(file, "{} {} {} {} {} {} {} {} {}", log.game, log.score, d.board.width, d.board.height,
+                      tiles.connect(" "), letter(d.action), d.picks.len(), picks.connect(" "),
+                      scores.connect(" ")));
+      }
+    }
+    Ok(())
+  }
+}
+
+//One line of save_decisions: the game, its final score and the decision
+fn parse_decision(words : &[&str]) -> Option<(uint, int, Decision)> {
+  if words.len() < 4 {
+    return None
+  }
+  let (game, score, width, height) = match (from_str::<uint>(words[0]), from_str::<int>(words[1]),
+                                            from_str::<int>(words[2]), from_str::<int>(words[3])) {
+    (Some(game), Some(score), Some(width), Some(height)) if width > 0 && height > 0 => (game, score, width, height),
+    _ => return None
+  };
+  let cells = (width * height) as uint;
+  if words.len() < cells + 6 {
+    return None
+  }
+  let mut board = Board::empty_sized(width, height);
+  for (cell, word) in board.vec.mut_iter().zip(words.slice(4, cells + 4).iter()) {
+    match from_str::<int>(*word) {
+      Some(tile) => *cell = tile,
+      None => return None
+    }
+  }
+  let (action, members) = match (from_letter(words[cells + 4]), from_str::<uint>(words[cells + 5])) {
+    (Some(action), Some(members)) => (action, members),
+    _ => return None
+  };
+  let rest = words.slice_from(cells + 6);
+  if rest.len() != members * 5 {
+    return None
+  }
+  let mut picks = vec!();
+  for word in rest.slice_to(members).iter() {
+    match from_letter(*word) {
+      Some(action) => picks.push(action),
+      None => return None
+    }
+  }
+  let mut scores = vec!();
+  for m in range(0, members) {
+    let mut member = [0f32, ..4];
+    for i in range(0u, 4) {
+      match from_str::<f32>(rest[members + 4 * m + i]) {
+        Some(score) => member[i] = score,
+        None => return None
+      }
+    }
+    scores.push(member);
+  }
+  Some((game, score, Decision { board : board, picks : picks, scores : scores, action : action }))
+}
+
+//Games written by Ensemble::save_decisions
+pub fn load_decisions(path : &Path) -> IoResult<Vec<GameLog>> {
+  let mut reader = BufferedReader::new(try!(File::open(path)));
+  let mut logs : Vec<GameLog> = vec!();
+  for line in reader.lines() {
+    let line = try!(line);
+    let words : Vec<&str> = line.as_slice().words().collect();
+    if words.len() == 0 {
+      continue
+    }
+    let (game, score, decision) = match parse_decision(words.as_slice()) {
+      Some(parsed) => parsed,
+      None => return Err(bad_line("bad decision line", line.as_slice()))
+    };
+    let same_game = match logs.last() {
+      Some(log) => log.game == game,
+      None => false
+    };
+    if !same_game {
+      logs.push(GameLog { game : game, score : score, decisions : vec!() });
+    }
+    logs.mut_last().unwrap().decisions.push(decision);
+  }
+  Ok(logs)
+}
+
+impl AIPlayer for Ensemble {
+  fn new_game(&mut self) {
+    self.pending.clear();
+    for member in self.members.mut_iter() {
+      member.new_game();
+    }
+  }
+
+  //Each member gets its own stream, so members that sample do not all draw the same numbers
+  fn reseed(&mut self, seed : u32, game : uint) {
+    self.game = game;
+    for (m, member) in self.members.mut_iter().enumerate() {
+      member.reseed(seed + 1 + m as u32, game);
+    }
+  }
+
+  fn next_action(&mut self, board : &Board) -> Action {
+    match self.combination {
+      MetaPolicy(ref weights) if weights.len() != self.members.len() => {
+        fail!("meta-policy has {} weights for {} members", weights.len(), self.members.len())
+      }
+      _ => ()
+    }
+    let legal = board.get_actions();
+    let mut totals = [0f32, ..4];
+    let mut picks = vec!();
+    let mut scores = vec!();
+    for (m, member) in self.members.mut_iter().enumerate() {
+      let (pick, scaled) = match self.combination {
+        MajorityVote => {
+          let action = member.next_action(board);
+          let mut vote = [0f32, ..4];
+          vote[action_index(action)] = 1.;
+          (action, vote)
+        }
+        _ => scaled_scores(member, board)
+      };
+      let weight = match self.combination {
+        MetaPolicy(ref weights) => *weights.get(m),
+        _ => 1.
+      };
+      for i in range(0u, 4) {
+        totals[i] += weight * scaled[i];
+      }
+      picks.push(pick);
+      scores.push(scaled);
+    }
+
+    //highest total, ties to the action picked by the earliest member
+    let mut best = None;
+    let mut best_total = 0.;
+    for &pick in picks.iter().chain(ACTIONS.iter()) {
+      if !legal.contains(&pick) {
+        continue
+      }
+      let total = totals[action_index(pick)];
+      if best.is_none() || total > best_total {
+        best_total = total;
+        best = Some(pick);
+      }
+    }
+    let action = best.unwrap();
+    self.pending.push(Decision { board : board.clone(), picks : picks, scores : scores, action : action });
+    action
+  }
+
+  fn observe(&mut self, action : Action, spawn : (uint, int), board : &Board) {
+    for member in self.members.mut_iter() {
+      member.observe(action, spawn, board);
+    }
+  }
+
+  fn end_game(&mut self, report : &Report) {
+    let decisions = mem::replace(&mut self.pending, vec!());
+    self.logs.lock().push(GameLog { game : self.game, score : report.score, decisions : decisions });
+    for member in self.members.mut_iter() {
+      member.end_game(report);
+    }
+  }
+}
+
+#[test]
+pub fn test_Ensemble_vote() {
+  use std::io::fs;
+  use ai::{FixedPriority, GreedyScore, Player};
+  use spawn::SeededSpawn;
+  use game::{Down, Left, Right, Up};
+  let mut board = Board::empty();
+  board.vec  = vec!(0, 2, 4, 2,
+                    0, 2, 0, 2,
+                    0, 4, 4, 2,
+                    0, 4, 2, 2);
+  let mut ensemble = Ensemble::new(MajorityVote);
+  ensemble.push("left", FixedPriority::new(vec!(Left, Down, Right, Up)));
+  ensemble.push("left again", FixedPriority::new(vec!(Left, Down, Right, Up)));
+  ensemble.push("down", FixedPriority::new(vec!(Down, Left, Right, Up)));
+  ensemble.push("greedy", GreedyScore::new());
+  let mut copy = ensemble.clone();
+  assert_eq!(copy.next_action(&board), Left);
+  assert_eq!(*copy.pending.get(0).picks.get(2), Down);
+
+  //finished games go to the log shared between clones, in game order
+  for game in range(0u, 2).rev() {
+    let mut copy = ensemble.clone();
+    copy.reseed(1, game);
+    let (report, _) = Player::run_game(&mut copy, &mut SeededSpawn::for_game(1, game as u32), false);
+    assert_eq!(report.moves, copy.logs.lock().last().unwrap().decisions.len());
+  }
+  let logs = ensemble.logs();
+  assert_eq!(logs.iter().map(|log| log.game).collect::<Vec<uint>>(), vec!(0, 1));
+  assert_eq!(logs.get(0).decisions.get(0).board, SeededSpawn::for_game(1, 0).start());
+  let wins = ensemble.wins();
+  assert_eq!(wins.get(0).val1(), wins.get(1).val1());
+  assert!(wins.get(0).val1() > 0);
+
+  let path = Path::new("test_decisions.txt");
+  ensemble.save_decisions(&path).unwrap();
+  let loaded = load_decisions(&path);
+  fs::unlink(&path).unwrap();
+  let loaded = loaded.unwrap();
+  assert_eq!(loaded.len(), 2);
+  assert_eq!(loaded.get(1).score, logs.get(1).score);
+  assert_eq!(loaded.get(1).decisions.len(), logs.get(1).decisions.len());
+  assert_eq!(loaded.get(1).decisions.get(3).board, logs.get(1).decisions.get(3).board);
+  assert_eq!(loaded.get(1).decisions.get(3).picks, logs.get(1).decisions.get(3).picks);
+}
+
+#[test]
+pub fn test_train_meta_policy() {
+  use game::{Left, Right};
+  let mut board = Board::empty();
+  board.vec  = vec!(0, 2, 4, 2,
+                    0, 2, 0, 2,
+                    0, 4, 4, 2,
+                    0, 4, 2, 2);
+  //member 0 likes left and member 1 right, the game that went left scored more
+  let decision = |action| Decision {
+    board : board.clone(),
+    picks : vec!(Left, Right),
+    scores : vec!([0., 0., 1., 0.], [0., 0., 0., 1.]),
+    action : action
+  };
+  let logs = vec!(GameLog { game : 0, score : 1000, decisions : vec!(decision(Left), decision(Left)) },
+                  GameLog { game : 1, score : 100, decisions : vec!(decision(Right)) });
+  let weights = train_meta_policy(&logs, 50, 0.5);
+  assert_eq!(weights.len(), 2);
+  assert!(*weights.get(0) > *weights.get(1));
+}
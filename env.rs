@@ -0,0 +1,288 @@
+use std::mem;
+
+use game::{Action, Board, Up, Down, Left, Right, exponent};
+use spawn::{SpawnPolicy, SeededSpawn};
+use pool::WorkerPool;
+
+//Actions in the order used by masks and action indices
+pub static ACTIONS : [Action, ..4] = [Up, Down, Left, Right];
+
+//Exponents 0 (empty) to 15 get their own plane, 32768 and larger tiles share
+//the last. The other encodings keep the full exponent.
+pub static NUM_PLANES : uint = 16;
+
+pub fn action_index(action : Action) -> uint {
+  ACTIONS.iter().position(|&a| a == action).unwrap()
+}
+
+//The one-hot plane of a tile
+fn plane(tile : int) -> uint {
+  let e = exponent(tile);
+  if e >= NUM_PLANES { NUM_PLANES - 1 } else { e }
+}
+
+//How the board is handed to the learner. Cells are in the order of Board::vec.
+#[deriving(Clone, Show)]
+pub enum Encoding {
+  RawExponents,
+  OneHotPlanes,
+  FlatVector
+}
+
+#[deriving(Clone, Show)]
+pub enum Observation {
+  //log2 of every tile, 0 for an empty space
+  Exponents(Vec<u8>),
+  //NUM_PLANES planes one after another, plane e is 1 where the exponent is e
+  Planes(Vec<f32>),
+  //The exponents as f32
+  Flat(Vec<f32>)
+}
+
+pub fn encode(board : &Board, encoding : Encoding) -> Observation {
+  match encoding {
+    RawExponents => Exponents(board.vec.iter().map(|&t| exponent(t) as u8).collect()),
+    OneHotPlanes => {
+      let cells = board.vec.len();
+      let mut planes = Vec::from_elem(NUM_PLANES * cells, 0f32);
+      for (i, &tile) in board.vec.iter().enumerate() {
+        *planes.get_mut(plane(tile) * cells + i) = 1.;
+      }
+      Planes(planes)
+    }
+    FlatVector => Flat(board.vec.iter().map(|&t| exponent(t) as f32).collect())
+  }
+}
+
+//Weights of the parts of the reward for one step
+#[deriving(Clone, Show)]
+pub struct RewardShaping {
+  //Per point of merge score
+  pub merge : f32,
+  //Per doubling of the best tile
+  pub max_tile : f32,
+  //For every move that does not end the game
+  pub survival : f32
+}
+
+impl RewardShaping {
+  //The game's own score
+  pub fn score() -> RewardShaping {
+    RewardShaping { merge : 1., max_tile : 0., survival : 0. }
+  }
+}
+
+#[deriving(Clone, Show)]
+pub struct StepInfo {
+  pub board : Board,
+  //Merge score of this step alone
+  pub merged : int,
+  pub score : int,
+  pub moves : uint,
+  //False if the action did not move anything, the step was then skipped
+  pub legal : bool
+}
+
+pub type Step = (Observation, f32, bool, StepInfo);
+
+//One game as a reinforcement learning environment. The rules and spawns are
+//the same as everywhere else in the crate, spawns come from a seeded rng so
+//an episode replays exactly from its seed.
+#[deriving(Clone)]
+pub struct Env {
+  width : int,
+  height : int,
+  shaping : RewardShaping,
+  encoding : Encoding,
+  spawner : SeededSpawn,
+  board : Board,
+  score : int,
+  moves : uint
+}
+
+impl Env {
+  pub fn new(width : int, height : int, shaping : RewardShaping, encoding : Encoding) -> Env {
+    Env {
+      width : width,
+      height : height,
+      shaping : shaping,
+      encoding : encoding,
+      spawner : SeededSpawn::new(0),
+      board : Board::empty_sized(width, height),
+      score : 0,
+      moves : 0
+    }
+  }
+
+  pub fn reset(&mut self, seed : u32) -> Observation {
+    self.spawner = SeededSpawn::new(seed);
+    let first = self.spawner.spawn(&Board::empty_sized(self.width, self.height));
+    self.board = self.spawner.spawn(&first);
+    self.score = 0;
+    self.moves = 0;
+    self.observe()
+  }
+
+  pub fn observe(&self) -> Observation {
+    encode(&self.board, self.encoding.clone())
+  }
+
+  pub fn board<'a>(&'a self) -> &'a Board {
+    &self.board
+  }
+
+  pub fn done(&self) -> bool {
+    self.board.get_actions().len() == 0
+  }
+
+  //Which of ACTIONS move anything
+  pub fn legal_mask(&self) -> [bool, ..4] {
+    let mut mask = [false, ..4];
+    for &action in self.board.get_actions().iter() {
+      mask[action_index(action)] = true;
+    }
+    mask
+  }
+
+  fn info(&self, merged : int, legal : bool) -> StepInfo {
+    StepInfo { board : self.board.clone(), merged : merged, score : self.score, moves : self.moves, legal : legal }
+  }
+
+  pub fn step(&mut self, action : Action) -> Step {
+    let (after, merged) = self.board.move_and_score(action);
+    if after == self.board {
+      let done = self.done();
+      return (self.observe(), 0., done, self.info(0, false))
+    }
+    let before = exponent(self.board.get_best_tile());
+    self.board = self.spawner.spawn(&after);
+    self.score += merged;
+    self.moves += 1;
+    let done = self.done();
+    let reward = self.shaping.merge * merged as f32 +
+      self.shaping.max_tile * (exponent(self.board.get_best_tile()) - before) as f32 +
+      if done { 0. } else { self.shaping.survival };
+    (self.observe(), reward, done, self.info(merged, true))
+  }
+}
+
+//Many environments stepped together, split over the tasks of a pool that
+//lives as long as the VecEnv
+pub struct VecEnv {
+  envs : Vec<Env>,
+  pool : WorkerPool
+}
+
+impl VecEnv {
+  pub fn new(count : uint, threads : uint, width : int, height : int, shaping : RewardShaping,
+             encoding : Encoding) -> VecEnv {
+    let env = Env::new(width, height, shaping, encoding);
+    VecEnv {
+      envs : Vec::from_elem(count, env),
+      pool : WorkerPool::new(threads)
+    }
+  }
+
+  pub fn len(&self) -> uint {
+    self.envs.len()
+  }
+
+  pub fn get<'a>(&'a self, i : uint) -> &'a Env {
+    self.envs.get(i)
+  }
+
+  //Environment i starts from seed + i
+  pub fn reset(&mut self, seed : u32) -> Vec<Observation> {
+    self.envs.mut_iter().enumerate().map(|(i, env)| env.reset(seed + i as u32)).collect()
+  }
+
+  //Start a new episode in one environment, usually after it is done
+  pub fn reset_env(&mut self, i : uint, seed : u32) -> Observation {
+    self.envs.get_mut(i).reset(seed)
+  }
+
+  pub fn legal_masks(&self) -> Vec<[bool, ..4]> {
+    self.envs.iter().map(|env| env.legal_mask()).collect()
+  }
+
+  //One action per environment, the steps come back in the same order
+  pub fn step(&mut self, actions : &Vec<Action>) -> Vec<Step> {
+    assert_eq!(actions.len(), self.envs.len());
+    let threads = self.pool.threads();
+    let per_thread = (self.envs.len() + threads - 1) / threads;
+    let envs = mem::replace(&mut self.envs, vec!());
+    let (tx, rx) : (Sender<(uint, Option<Vec<(Env, Step)>>)>,
+                    Receiver<(uint, Option<Vec<(Env, Step)>>)>) = channel();
+    let mut chunks = 0;
+    let mut chunk = vec!();
+    let mut work = envs.move_iter().zip(actions.iter().map(|&a| a)).peekable();
+    while !work.is_empty() {
+      chunk.push(work.next().unwrap());
+      if chunk.len() == per_thread || work.is_empty() {
+        let chunk = mem::replace(&mut chunk, vec!());
+        self.pool.run(chunks, proc() {
+          chunk.move_iter().map(|(mut env, action)| {
+            let step = env.step(action);
+            (env, step)
+          }).collect()
+        }, &tx);
+        chunks += 1;
+      }
+    }
+    let mut parts : Vec<(uint, Vec<(Env, Step)>)> = range(0, chunks).map(|_| match rx.recv() {
+      (idx, Some(part)) => (idx, part),
+      (idx, None) => fail!("stepping chunk {} of the environments failed", idx)
+    }).collect();
+    parts.sort_by(|&(a, _), &(b, _)| a.cmp(&b));
+    let mut steps = vec!();
+    for (_, part) in parts.move_iter() {
+      for (env, step) in part.move_iter() {
+        self.envs.push(env);
+        steps.push(step);
+      }
+    }
+    steps
+  }
+}
+
+#[test]
+pub fn test_Env_seeded() {
+  let mut a = Env::new(4, 4, RewardShaping::score(), RawExponents);
+  let mut b = Env::new(4, 4, RewardShaping::score(), RawExponents);
+  a.reset(3);
+  b.reset(3);
+  assert_eq!(a.board(), b.board());
+  let mask = a.legal_mask();
+  for &action in ACTIONS.iter() {
+    assert_eq!(mask[action_index(action)], a.board().get_actions().contains(&action));
+  }
+  let action = *a.board().get_actions().get(0);
+  let (_, reward, _, info) = a.step(action);
+  assert!(info.legal);
+  assert_eq!(reward, info.merged as f32);
+  b.step(action);
+  assert_eq!(a.board(), b.board());
+
+  //a vectorized step gives the same boards as stepping one by one
+  let mut v = VecEnv::new(5, 2, 4, 4, RewardShaping::score(), OneHotPlanes);
+  v.reset(3);
+  let mut single = v.get(4).clone();
+  let actions = v.envs.iter().map(|env| *env.board().get_actions().get(0)).collect();
+  let steps = v.step(&actions);
+  assert_eq!(steps.len(), 5);
+  assert_eq!(v.get(0).board(), a.board());
+  single.step(*actions.get(4));
+  assert_eq!(v.get(4).board(), single.board());
+
+  //tiles past the last plane keep their exponent in the other encodings
+  let mut board = Board::empty();
+  *board.vec.get_mut(0) = 131072;
+  match encode(&board, RawExponents) {
+    Exponents(e) => assert_eq!(*e.get(0), 17),
+    _ => fail!()
+  }
+  match encode(&board, OneHotPlanes) {
+    Planes(p) => assert_eq!(*p.get((NUM_PLANES - 1) * 16), 1.),
+    _ => fail!()
+  }
+}
@@ -0,0 +1,143 @@
+use std::collections::HashMap;
+use std::num::Float;
+use std::mem;
+
+use game::{Action, Board};
+use ai::{AIPlayer, Evaluator};
+
+//Memory the memo may take before it is cleared
+static MAX_MEMO_BYTES : uint = 64 << 20;
+
+//(packed board, board width, board height, depth, is a chance node)
+type MemoKey = (u64, int, int, uint, bool);
+
+//Entries that fit in MAX_MEMO_BYTES, counting the hash stored with each
+fn max_memo() -> uint {
+  MAX_MEMO_BYTES / (mem::size_of::<(MemoKey, f32)>() + mem::size_of::<u64>())
+}
+
+fn memo_key(board : &Board, depth : uint, chance : bool) -> Option<MemoKey> {
+  board.pack().map(|packed| (packed, board.width, board.height, depth, chance))
+}
+
+//Expectimax without sampling: every empty space gets both a 2 and a 4 with
+//their real odds. Depth is counted like ExpectiMax so the two line up, and
+//values are memoized by position so transpositions are only searched once.
+#[deriving(Clone)]
+pub struct ExactExpectiMax<E> {
+  max_depth : uint,
+  evaluator : E,
+  //Values of positions that Board::pack fits, the rest are not memoized
+  memo : HashMap<MemoKey, f32>
+}
+
+impl<E : Evaluator> ExactExpectiMax<E> {
+  pub fn new(max_depth : uint, evaluator : E) -> ExactExpectiMax<E> {
+    ExactExpectiMax { max_depth : max_depth, evaluator : evaluator, memo : HashMap::new() }
+  }
+
+  fn recall(&self, key : &Option<MemoKey>) -> Option<f32> {
+    match *key {
+      Some(ref key) => self.memo.find(key).map(|&value| value),
+      None => None
+    }
+  }
+
+  fn remember(&mut self, key : Option<MemoKey>, value : f32) -> f32 {
+    match key {
+      Some(key) => {
+        if self.memo.len() >= max_memo() {
+          self.memo.clear();
+        }
+        self.memo.insert(key, value);
+      }
+      None => ()
+    }
+    value
+  }
+
+  pub fn max_value(&mut self, board : &Board, depth : uint) -> f32 {
+    let key = memo_key(board, depth, false);
+    match self.recall(&key) {
+      Some(value) => return value,
+      None => ()
+    }
+    let actions = board.get_actions();
+    let value = if depth == self.max_depth || actions.len() == 0 {
+      self.evaluator.evaluate(board)
+    } else {
+      let inf : f32 = Float::infinity();
+      actions.iter().fold(-inf, |best, &action| {
+        best.max(self.chance_value(&board.move(action), depth + 1))
+      })
+    };
+    self.remember(key, value)
+  }
+
+  pub fn chance_value(&mut self, board : &Board, depth : uint) -> f32 {
+    let key = memo_key(board, depth, true);
+    match self.recall(&key) {
+      Some(value) => return value,
+      None => ()
+    }
+    let value = if depth == self.max_depth || board.get_actions().len() == 0 {
+      self.evaluator.evaluate(board)
+    } else {
+      let empty = board.get_empty();
+      let p = 1. / empty.len() as f32;
+      empty.iter().fold(0., |total, &indx| {
+        total +
+          0.9 * p * self.max_value(&board.add_space((indx, 2, 0.)), depth + 1) +
+          0.1 * p * self.max_value(&board.add_space((indx, 4, 0.)), depth + 1)
+      })
+    };
+    self.remember(key, value)
+  }
+
+  //Exact value of every legal action from board
+  pub fn action_values(&mut self, board : &Board) -> Vec<(Action, f32)> {
+    board.get_actions().iter().map(|&action| {
+      (action, self.chance_value(&board.move(action), 1))
+    }).collect()
+  }
+}
+
+impl<E : Evaluator> AIPlayer for ExactExpectiMax<E> {
+  fn next_action(&mut self, board : &Board) -> Action {
+    let inf : f32 = Float::infinity();
+    let mut best_value = -inf;
+    let mut best = None;
+    for &(action, value) in self.action_values(board).iter() {
+      if value > best_value {
+        best_value = value;
+        best = Some(action);
+      }
+    }
+    best.unwrap()
+  }
+
+  fn action_scores(&mut self, board : &Board) -> Option<Vec<(Action, f32)>> {
+    Some(self.action_values(board))
+  }
+}
+
+#[test]
+pub fn test_ExactExpectiMax_depth_one() {
+  use ai::Heuristic;
+  let mut board = Board::empty();
+  board.vec  = vec!(0, 2, 4, 2,
+                    0, 2, 0, 2,
+                    0, 4, 4, 2,
+                    0, 4, 2, 2);
+  let h = Heuristic::default();
+  //with one ply the value of a move is just its resulting board
+  let mut e = ExactExpectiMax::new(1, h.clone());
+  for &(action, value) in e.action_values(&board).iter() {
+    assert_eq!(value, h.evaluate(&board.move(action)));
+  }
+  //memoized values come back unchanged
+  let mut e = ExactExpectiMax::new(3, h.clone());
+  let first = e.action_values(&board);
+  let second = e.action_values(&board);
+  assert_eq!(first, second);
+}
@@ -21,13 +21,13 @@ impl Action {
     }
   }
 
-  pub fn min_cord(&self, start : Cord) -> Cord {
+  pub fn min_cord(&self, start : Cord, width : int, height : int) -> Cord {
     let Cord(x, y) = start;
     match *self {
       Up => Cord(x, 0),
-      Down => Cord(x, 3),
+      Down => Cord(x, height - 1),
       Left => Cord(0, y),
-      Right => Cord(3, y)
+      Right => Cord(width - 1, y)
     }
   }
 }
@@ -36,45 +36,66 @@ impl Action {
 pub struct Cord(pub int, pub int);
 
 impl Cord {
-  pub fn is_valid(&self) -> bool {
+  pub fn is_valid(&self, width : int, height : int) -> bool {
     let Cord(x, y) = *self;
     if (x < 0) || (y < 0) {
       false
-    } else if (x >= 4) || (y >= 4) {
+    } else if (x >= width) || (y >= height) {
       false
     } else {
       true
     }
   }
 
-  pub fn over(&self, action : Action) -> Option<Cord> {
+  pub fn over(&self, action : Action, width : int, height : int) -> Option<Cord> {
     let (dx, dy) = action.dir();
     let Cord(x,y) = *self;
     let over = Cord(x+dx, y+dy);
-    if over.is_valid() {
+    if over.is_valid(width, height) {
       Some(over)
     } else {
       None
     }
   }
+
+  //One of the 8 symmetries of a 4x4 board, 0 is the identity
+  pub fn transform(&self, sym : uint) -> Cord {
+    let Cord(x, y) = *self;
+    let (x, y) = if sym & 4 != 0 { (y, x) } else { (x, y) };
+    let x = if sym & 1 != 0 { 3 - x } else { x };
+    let y = if sym & 2 != 0 { 3 - y } else { y };
+    Cord(x, y)
+  }
+}
+
+//log2 of a tile, 0 for an empty space
+pub fn exponent(value : int) -> uint {
+  let mut value = value;
+  let mut e = 0;
+  while value > 1 {
+    value /= 2;
+    e += 1;
+  }
+  e
 }
 
 //TODO compute this once
-fn get_traversal(action : Action) -> Vec<Cord> {
+fn get_traversal(action : Action, width : int, height : int) -> Vec<Cord> {
+  let size = (width * height) as uint;
   match action {
-    Up =>Vec::from_fn(16, |x| {
-      Cord((x / 4) as int, (x % 4) as int)
+    Up =>Vec::from_fn(size, |x| {
+      Cord(x as int / height, x as int % height)
     }),
     Down => {
-      let mut ret = get_traversal(Up);
+      let mut ret = get_traversal(Up, width, height);
       ret.reverse();
       ret
     }
-    Left => Vec::from_fn(16, |x| {
-      Cord((x % 4) as int, (x / 4) as int)
+    Left => Vec::from_fn(size, |x| {
+      Cord(x as int % width, x as int / width)
     }),
     Right => {
-      let mut ret = get_traversal(Left);
+      let mut ret = get_traversal(Left, width, height);
       ret.reverse();
       ret
     }
@@ -83,7 +104,7 @@ fn get_traversal(action : Action) -> Vec<Cord> {
 
 fn get_first_free(start_cord: Cord, action : Action, board : &Board) -> Cord {
   let (dx, dy) = action.dir();
-  let mut on_cord = action.min_cord(start_cord);
+  let mut on_cord = action.min_cord(start_cord, board.width, board.height);
   let mut ret = start_cord;
   while on_cord != start_cord {
     let Cord(x,y) = on_cord;
@@ -97,19 +118,30 @@ fn get_first_free(start_cord: Cord, action : Action, board : &Board) -> Cord {
 }
 
 
-#[deriving(Clone, Eq)]
+//Tiles are stored row by row, 0 is an empty space
+#[deriving(Clone, Eq, Hash)]
 pub struct Board {
-  pub vec : Vec<int>
+  pub vec : Vec<int>,
+  pub width : int,
+  pub height : int
 }
 
 impl Board {
   pub fn empty() -> Board {
-    let vec = Vec::from_elem(16, 0);
-    Board { vec : vec }
+    Board::empty_sized(4, 4)
+  }
+
+  pub fn empty_sized(width : int, height : int) -> Board {
+    let vec = Vec::from_elem((width * height) as uint, 0);
+    Board { vec : vec, width : width, height : height }
   }
 
   pub fn new() -> Board {
-    let empty = Board::empty();
+    Board::new_sized(4, 4)
+  }
+
+  pub fn new_sized(width : int, height : int) -> Board {
+    let empty = Board::empty_sized(width, height);
     empty.add_random().add_random()
   }
 
@@ -133,15 +165,50 @@ impl Board {
     new
   }
 
+  //The tile exponents at 4 bits per cell in the order of vec. None if the
+  //board has more than 16 cells or a tile above 32768. Boards of different
+  //shapes with the same cells pack the same.
+  pub fn pack(&self) -> Option<u64> {
+    if self.vec.len() > 16 {
+      return None
+    }
+    let mut packed = 0u64;
+    for (i, &tile) in self.vec.iter().enumerate() {
+      let e = exponent(tile);
+      if e > 15 {
+        return None
+      }
+      packed |= (e as u64) << (4 * i);
+    }
+    Some(packed)
+  }
+
+  //The board that packed to packed, given its shape
+  pub fn unpack(packed : u64, width : int, height : int) -> Board {
+    let mut board = Board::empty_sized(width, height);
+    for (i, tile) in board.vec.mut_iter().enumerate() {
+      let e = (packed >> (4 * i)) & 15;
+      *tile = if e == 0 { 0 } else { 1 << e as uint };
+    }
+    board
+  }
+
   pub fn move(&self, action : Action) -> Board {
-    let mut new = Board::empty();
-    let mut merged = Board::empty();
-    for &cord in get_traversal(action).iter() {
+    let (new, _) = self.move_and_score(action);
+    new
+  }
+
+  //Same as move but also returns the sum of the tiles created by merging
+  pub fn move_and_score(&self, action : Action) -> (Board, int) {
+    let mut score = 0;
+    let mut new = Board::empty_sized(self.width, self.height);
+    let mut merged = Board::empty_sized(self.width, self.height);
+    for &cord in get_traversal(action, self.width, self.height).iter() {
       match self.get(cord) {
         0 => (),
         value => {
           let first_free = get_first_free(cord, action, &new);
-          let next_cord = first_free.over(action);
+          let next_cord = first_free.over(action, self.width, self.height);
           match next_cord {
             None => {
               *new.get_mut(first_free) = value;
@@ -150,6 +217,7 @@ impl Board {
               if new.get(over) == value && merged.get(over) == 0 {
                 *new.get_mut(over) = value*2;
                 *merged.get_mut(over) = 1;
+                score += value*2;
               } else {
                 *new.get_mut(first_free) = value;
               }
@@ -158,23 +226,27 @@ impl Board {
         }
       }
     }
-    new
+    (new, score)
   }
 
   pub fn add_random(&self) -> Board {
-    let r : f32 = rand::random();
+    self.add_random_with(&mut rand::task_rng())
+  }
+
+  pub fn add_random_with<R : Rng>(&self, rng : &mut R) -> Board {
+    let r : f32 = rng.gen();
     let value = if r < 0.9f32 { 2 } else { 4 };
-    let mut vec = self.vec.clone();
+    let mut new = self.clone();
     let empty = self.get_empty();
-    let indx = rand::task_rng().choose(empty.as_slice());
+    let indx = rng.choose(empty.as_slice());
     let indx = *indx.unwrap();
-    *vec.get_mut(indx) = value;
-    Board { vec : vec }
+    *new.vec.get_mut(indx) = value;
+    new
   }
 
   pub fn get(&self, c : Cord) -> int {
-    if c.is_valid() {
-      let Cord(x, y) = c; *self.vec.get((x as uint) + (y*4) as uint)
+    if c.is_valid(self.width, self.height) {
+      let Cord(x, y) = c; *self.vec.get((x + y*self.width) as uint)
     } else {
       fail!("cord invalid {}", c);
     }
@@ -182,7 +254,7 @@ impl Board {
 
   pub fn get_mut<'a>(&'a mut self, c : Cord) -> &'a mut int {
     let Cord(x, y) = c;
-    self.vec.get_mut((x as uint) + (y*4) as uint)
+    self.vec.get_mut((x + y*self.width) as uint)
   }
 
   pub fn count_empty(&self) -> int {
@@ -224,8 +296,8 @@ impl Board {
 impl fmt::Show for Board {
   fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
     let _ = write!(f, "\n");
-    for y in range(0, 4) {
-      for x in range(0, 4) {
+    for y in range(0, self.height) {
+      for x in range(0, self.width) {
         let _ = write!(f, "{:5} ", self.get(Cord(x, y)));
       }
       let _ = write!(f, "\n");
@@ -288,8 +360,16 @@ mod test {
     assert_eq!(actions, vec!(Right));
   }
 
+  #[test]
+  fn test_Board_pack_round_trip() {
+    let mut board = Board::empty_sized(3, 2);
+    board.vec = vec!(2, 0, 4,
+                     0, 32768, 8);
+    assert_eq!(Board::unpack(board.pack().unwrap(), 3, 2), board);
+  }
+
   mod move {
-    use super::super::{Board, Left, Up, Down, Cord};
+    use super::super::{Board, Left, Right, Up, Down, Cord};
     #[test]
     pub fn test_simple_move() {
       let mut board = Board::empty();
@@ -359,6 +439,35 @@ mod test {
                       0, 0, 0, 0);
       assert_eq!(board_up.vec, up);
     }
+
+    #[test]
+    pub fn test_small_board_move() {
+      let mut board = Board::empty_sized(3, 2);
+      board.vec = vec!(2, 0, 2,
+                       4, 4, 0);
+      let right = board.move(Right);
+      assert_eq!(right.vec, vec!(0, 0, 4,
+                                 0, 0, 8));
+      let down = board.move(Down);
+      assert_eq!(down.vec, vec!(2, 0, 0,
+                                4, 4, 2));
+    }
+
+    #[test]
+    pub fn test_move_and_score() {
+      let mut board = Board::empty();
+      *board.get_mut(Cord(0,0)) = 2;
+      *board.get_mut(Cord(1,0)) = 2;
+      *board.get_mut(Cord(2,0)) = 8;
+      *board.get_mut(Cord(3,0)) = 8;
+      let (board_left, score) = board.move_and_score(Left);
+      let left = vec!(4, 16, 0, 0,
+                      0, 0, 0, 0,
+                      0, 0, 0, 0,
+                      0, 0, 0, 0);
+      assert_eq!(board_left.vec, left);
+      assert_eq!(score, 20);
+    }
   }
 }
 
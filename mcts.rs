@@ -0,0 +1,276 @@
+use std::num::Float;
+use rand::{Rng, XorShiftRng};
+
+use game::{Action, Board};
+use ai::{AIPlayer, Evaluator, seeded_rng, random_rng};
+
+//How a newly reached position is valued
+#[deriving(Clone)]
+pub enum LeafEvaluation<E> {
+  //Score of a random playout, limited to some number of moves
+  Rollout(Option<uint>),
+  Evaluate(E)
+}
+
+#[deriving(Clone, Show)]
+pub struct ActionStats {
+  pub action : Action,
+  pub visits : uint,
+  pub mean : f32
+}
+
+//A position where the player moves
+#[deriving(Clone)]
+struct DecisionNode {
+  board : Board,
+  visits : uint,
+  expanded : bool,
+  children : Vec<ChanceNode>
+}
+
+//The board after a move, waiting for a tile to spawn
+#[deriving(Clone)]
+struct ChanceNode {
+  action : Action,
+  reward : f32,
+  afterstate : Board,
+  visits : uint,
+  total : f32,
+  outcomes : Vec<((uint, int), DecisionNode)>
+}
+
+impl DecisionNode {
+  fn new(board : Board) -> DecisionNode {
+    DecisionNode { board : board, visits : 0, expanded : false, children : vec!() }
+  }
+}
+
+impl ChanceNode {
+  fn mean(&self) -> f32 {
+    if self.visits == 0 { 0. } else { self.total / self.visits as f32 }
+  }
+
+  //Draw a spawn with the game's odds and return its node, creating it if needed
+  fn sample(&mut self, rng : &mut XorShiftRng) -> &mut DecisionNode {
+    let empty = self.afterstate.get_empty();
+    let cell = *rng.choose(empty.as_slice()).unwrap();
+    let r : f32 = rng.gen();
+    let value = if r < 0.9 { 2 } else { 4 };
+    let position = self.outcomes.iter().position(|&(spawn, _)| spawn == (cell, value));
+    let index = match position {
+      Some(index) => index,
+      None => {
+        let board = self.afterstate.add_space((cell, value, 0.));
+        self.outcomes.push(((cell, value), DecisionNode::new(board)));
+        self.outcomes.len() - 1
+      }
+    };
+    match *self.outcomes.get_mut(index) {
+      (_, ref mut node) => node
+    }
+  }
+}
+
+//Monte Carlo tree search with UCT at decision nodes and sampled spawns at
+//chance nodes. Values are the game score earned inside the tree plus the
+//leaf evaluation.
+#[deriving(Clone)]
+pub struct Mcts<E> {
+  simulations : uint, //Simulations per move
+  exploration : f32,
+  leaf : LeafEvaluation<E>,
+  //Tree from the last move, kept so the next move can start from a subtree
+  root : Option<DecisionNode>,
+  rng : XorShiftRng //Spawns and rollout moves
+}
+
+impl<E : Evaluator> Mcts<E> {
+  pub fn new(simulations : uint, exploration : f32, leaf : LeafEvaluation<E>) -> Mcts<E> {
+    Mcts {
+      //the root has no children to pick from until a simulation expands it
+      simulations : if simulations == 0 { 1 } else { simulations },
+      exploration : exploration,
+      leaf : leaf,
+      root : None,
+      rng : random_rng()
+    }
+  }
+
+  fn leaf_value(&self, board : &Board, rng : &mut XorShiftRng) -> f32 {
+    match self.leaf {
+      Evaluate(ref evaluator) => evaluator.evaluate(board),
+      Rollout(max_depth) => {
+        let mut board = board.clone();
+        let mut score = 0;
+        let mut moves = 0;
+        loop {
+          match max_depth {
+            Some(depth) if moves >= depth => break,
+            _ => ()
+          }
+          let actions = board.get_actions();
+          if actions.len() == 0 {
+            break
+          }
+          let action = *rng.choose(actions.as_slice()).unwrap();
+          let (after, reward) = board.move_and_score(action);
+          score += reward;
+          board = after.add_random_with(rng);
+          moves += 1;
+        }
+        score as f32
+      }
+    }
+  }
+
+  //UCT over the children, with means scaled to [0, 1] using the range seen here
+  fn select(&self, node : &DecisionNode) -> uint {
+    let inf : f32 = Float::infinity();
+    let mut low = inf;
+    let mut high = -inf;
+    for child in node.children.iter() {
+      if child.visits == 0 {
+        continue
+      }
+      low = low.min(child.mean());
+      high = high.max(child.mean());
+    }
+    let range = if high > low { high - low } else { 1. };
+    let log_visits = (node.visits as f32).ln();
+
+    let mut best = 0;
+    let mut best_value = -inf;
+    for (idx, child) in node.children.iter().enumerate() {
+      let value = if child.visits == 0 {
+        inf
+      } else {
+        (child.mean() - low) / range +
+          self.exploration * (log_visits / child.visits as f32).sqrt()
+      };
+      if value > best_value {
+        best_value = value;
+        best = idx;
+      }
+    }
+    best
+  }
+
+  fn simulate(&self, node : &mut DecisionNode, rng : &mut XorShiftRng) -> f32 {
+    node.visits += 1;
+    if !node.expanded {
+      node.expanded = true;
+      for &action in node.board.get_actions().iter() {
+        let (afterstate, reward) = node.board.move_and_score(action);
+        node.children.push(ChanceNode {
+          action : action,
+          reward : reward as f32,
+          afterstate : afterstate,
+          visits : 0,
+          total : 0.,
+          outcomes : vec!()
+        });
+      }
+      return self.leaf_value(&node.board, rng)
+    }
+    if node.children.len() == 0 {
+      return self.leaf_value(&node.board, rng)
+    }
+    let idx = self.select(node);
+    let child = node.children.get_mut(idx);
+    let value = child.reward + self.simulate(child.sample(rng), rng);
+    child.visits += 1;
+    child.total += value;
+    value
+  }
+
+  //Reuse the subtree for board if the last search reached it
+  fn take_subtree(&mut self, board : &Board) -> DecisionNode {
+    let old = self.root.take();
+    match old {
+      Some(root) => {
+        for child in root.children.move_iter() {
+          for (_, node) in child.outcomes.move_iter() {
+            if node.board == *board {
+              return node
+            }
+          }
+        }
+        DecisionNode::new(board.clone())
+      }
+      None => DecisionNode::new(board.clone())
+    }
+  }
+
+  //Visits and mean value of each root action from the last search
+  pub fn root_stats(&self) -> Vec<ActionStats> {
+    match self.root {
+      Some(ref root) => root.children.iter().map(|child| {
+        ActionStats { action : child.action, visits : child.visits, mean : child.mean() }
+      }).collect(),
+      None => vec!()
+    }
+  }
+}
+
+impl<E : Evaluator> AIPlayer for Mcts<E> {
+  //Nothing from the last game's tree can be reused
+  fn new_game(&mut self) {
+    self.root = None;
+  }
+
+  fn reseed(&mut self, seed : u32, game : uint) {
+    self.rng = seeded_rng(seed, game);
+  }
+
+  fn next_action(&mut self, board : &Board) -> Action {
+    let mut root = self.take_subtree(board);
+    let mut rng = self.rng.clone();
+    for _ in range(0, self.simulations) {
+      self.simulate(&mut root, &mut rng);
+    }
+    self.rng = rng;
+    let mut best = None;
+    let mut best_visits = 0;
+    for child in root.children.iter() {
+      if best.is_none() || child.visits > best_visits {
+        best_visits = child.visits;
+        best = Some(child.action);
+      }
+    }
+    self.root = Some(root);
+    best.unwrap()
+  }
+
+  //Root visit counts, which is what next_action picks by
+  fn action_scores(&mut self, board : &Board) -> Option<Vec<(Action, f32)>> {
+    self.next_action(board);
+    Some(self.root_stats().iter().map(|s| (s.action, s.visits as f32)).collect())
+  }
+}
+
+#[test]
+pub fn test_Mcts_root_stats() {
+  use ai::Heuristic;
+  let mut board = Board::empty();
+  board.vec  = vec!(0, 2, 4, 2,
+                    0, 2, 0, 2,
+                    0, 4, 4, 2,
+                    0, 4, 2, 2);
+  let mut m = Mcts::new(50, 1.4, Evaluate(Heuristic::default()));
+  let action = m.next_action(&board);
+  let stats = m.root_stats();
+  assert_eq!(stats.len(), board.get_actions().len());
+  let visits = stats.iter().fold(0, |total, s| total + s.visits);
+  //the first simulation only expands the root
+  assert_eq!(visits, 49);
+  let chosen = stats.iter().find(|s| s.action == action).unwrap();
+  assert!(stats.iter().all(|s| s.visits <= chosen.visits));
+}
+
+#[test]
+pub fn test_Mcts_no_simulations() {
+  use ai::Heuristic;
+  let board = Board::new();
+  let mut m = Mcts::new(0, 1.4, Evaluate(Heuristic::default()));
+  assert!(board.get_actions().contains(&m.next_action(&board)));
+}
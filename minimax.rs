@@ -0,0 +1,169 @@
+use std::num::Float;
+use std::cmp::{Ordering, Less, Greater, Equal, min, max};
+use std::collections::HashMap;
+
+use game::{Action, Board};
+use ai::{AIPlayer, Evaluator};
+
+fn compare(a : f32, b : f32) -> Ordering {
+  if a < b { Less } else if a > b { Greater } else { Equal }
+}
+
+//Every tile the spawner could place on board
+pub fn spawns(board : &Board) -> Vec<Board> {
+  let mut boards = vec!();
+  for &indx in board.get_empty().iter() {
+    boards.push(board.add_space((indx, 2, 0.)));
+    boards.push(board.add_space((indx, 4, 0.)));
+  }
+  boards
+}
+
+//Minimax with alpha-beta pruning, assuming every spawn is the worst one
+//for the player. Depth counts both move and spawn layers like ExpectiMax.
+#[deriving(Clone)]
+pub struct Minimax<E> {
+  max_depth : uint,
+  evaluator : E
+}
+
+impl<E : Evaluator> Minimax<E> {
+  pub fn new(max_depth : uint, evaluator : E) -> Minimax<E> {
+    Minimax { max_depth : max_depth, evaluator : evaluator }
+  }
+
+  //Children sorted by their static value, best for the side to move first
+  fn ordered(&self, boards : Vec<Board>, best_first : bool) -> Vec<Board> {
+    let mut scored : Vec<(f32, Board)> = boards.move_iter().map(|b| {
+      (self.evaluator.evaluate(&b), b)
+    }).collect();
+    scored.sort_by(|&(a, _), &(b, _)| {
+      if best_first { compare(b, a) } else { compare(a, b) }
+    });
+    scored.move_iter().map(|(_, b)| b).collect()
+  }
+
+  pub fn max_layer(&self, board : &Board, depth : uint, alpha : f32, beta : f32) -> f32 {
+    let actions = board.get_actions();
+    if depth == self.max_depth || actions.len() == 0 {
+      return self.evaluator.evaluate(board)
+    }
+    let children = self.ordered(actions.iter().map(|&a| board.move(a)).collect(), true);
+    let inf : f32 = Float::infinity();
+    let mut alpha = alpha;
+    let mut value = -inf;
+    for child in children.iter() {
+      value = value.max(self.min_layer(child, depth + 1, alpha, beta));
+      if value >= beta {
+        return value
+      }
+      alpha = alpha.max(value);
+    }
+    value
+  }
+
+  pub fn min_layer(&self, board : &Board, depth : uint, alpha : f32, beta : f32) -> f32 {
+    if depth == self.max_depth {
+      return self.evaluator.evaluate(board)
+    }
+    let children = self.ordered(spawns(board), false);
+    let inf : f32 = Float::infinity();
+    let mut beta = beta;
+    let mut value = inf;
+    for child in children.iter() {
+      value = value.min(self.max_layer(child, depth + 1, alpha, beta));
+      if value <= alpha {
+        return value
+      }
+      beta = beta.min(value);
+    }
+    value
+  }
+}
+
+impl<E : Evaluator> AIPlayer for Minimax<E> {
+  fn next_action(&mut self, board : &Board) -> Action {
+    let inf : f32 = Float::infinity();
+    let mut alpha = -inf;
+    let mut best = None;
+    for &action in board.get_actions().iter() {
+      let value = self.min_layer(&board.move(action), 1, alpha, inf);
+      if best.is_none() || value > alpha {
+        alpha = value;
+        best = Some(action);
+      }
+    }
+    best.unwrap()
+  }
+}
+
+//True if the player can keep moving for k more moves whatever spawns
+pub fn survives(board : &Board, k : uint) -> bool {
+  safe_moves(board, k) == k
+}
+
+//The largest k up to limit for which board survives
+pub fn safe_moves(board : &Board, limit : uint) -> uint {
+  safe_moves_memo(board, limit, &mut HashMap::new())
+}
+
+//The best move keeps the most moves safe against its worst spawn. Every
+//depth comes out of the one search, and positions reached by different
+//orders of moves are only searched once.
+fn safe_moves_memo(board : &Board, limit : uint, memo : &mut HashMap<(Board, uint), uint>) -> uint {
+  if limit == 0 {
+    return 0
+  }
+  let key = (board.clone(), limit);
+  match memo.find(&key) {
+    Some(&k) => return k,
+    None => ()
+  }
+  let mut best = 0;
+  for &action in board.get_actions().iter() {
+    let mut worst = limit - 1;
+    for next in spawns(&board.move(action)).iter() {
+      worst = min(worst, safe_moves_memo(next, limit - 1, memo));
+      if worst + 1 <= best {
+        break
+      }
+    }
+    best = max(best, worst + 1);
+    if best == limit {
+      break
+    }
+  }
+  memo.insert(key, best);
+  best
+}
+
+#[test]
+pub fn test_survives() {
+  let mut board = Board::empty();
+  board.vec  = vec!(2, 4, 2, 4,
+                    4, 2, 4, 2,
+                    2, 4, 2, 4,
+                    4, 2, 4, 2);
+  assert!(survives(&board, 0));
+  assert!(!survives(&board, 1));
+  assert_eq!(safe_moves(&board, 3), 0);
+  board.vec  = vec!(2, 4, 2, 4,
+                    4, 2, 4, 2,
+                    2, 4, 2, 4,
+                    4, 2, 4, 0);
+  assert!(survives(&board, 1));
+  assert!(safe_moves(&board, 3) >= 1);
+}
+
+#[test]
+pub fn test_Minimax_simple() {
+  use ai::Heuristic;
+  let mut board = Board::empty();
+  board.vec  = vec!(0, 2, 4, 2,
+                    0, 2, 0, 2,
+                    0, 4, 4, 2,
+                    0, 4, 2, 2);
+  let mut m = Minimax::new(3, Heuristic::default());
+  let action = m.next_action(&board);
+  assert!(board.get_actions().contains(&action));
+}
@@ -0,0 +1,175 @@
+use std::num::Float;
+use rand::{Rng, XorShiftRng};
+
+use game::{Action, Board};
+use ai::{AIPlayer, seeded_rng, random_rng};
+
+//How moves are picked during a playout
+#[deriving(Clone, Show)]
+pub enum RolloutPolicy {
+  RandomRollout,
+  //Take the move that leaves the most empty spaces, ties broken randomly
+  EmptyRollout
+}
+
+//What a finished playout is worth
+#[deriving(Clone, Show)]
+pub enum RolloutOutcome {
+  RolloutScore,
+  RolloutMaxTile,
+  //1 if the playout reached the depth limit without the game ending
+  RolloutSurvival
+}
+
+#[deriving(Clone)]
+pub struct MonteCarlo {
+  rollouts : uint, //Playouts per legal action, at least 1
+  max_depth : Option<uint>, //Moves per playout, None to play to game over
+  policy : RolloutPolicy,
+  outcome : RolloutOutcome,
+  threads : uint,
+  rng : XorShiftRng //Seeds the rng of each thread's playouts
+}
+
+impl MonteCarlo {
+  pub fn new(rollouts : uint, max_depth : Option<uint>, policy : RolloutPolicy,
+             outcome : RolloutOutcome, threads : uint) -> MonteCarlo {
+    MonteCarlo {
+      rollouts : if rollouts == 0 { 1 } else { rollouts },
+      max_depth : max_depth,
+      policy : policy,
+      outcome : outcome,
+      threads : if threads == 0 { 1 } else { threads },
+      rng : random_rng()
+    }
+  }
+
+  fn pick(&self, board : &Board, actions : &Vec<Action>, rng : &mut XorShiftRng) -> Action {
+    match self.policy {
+      RandomRollout => *rng.choose(actions.as_slice()).unwrap(),
+      EmptyRollout => {
+        let mut best = vec!();
+        let mut best_empty = -1;
+        for &action in actions.iter() {
+          let empty = board.move(action).count_empty();
+          if empty > best_empty {
+            best_empty = empty;
+            best = vec!(action);
+          } else if empty == best_empty {
+            best.push(action);
+          }
+        }
+        *rng.choose(best.as_slice()).unwrap()
+      }
+    }
+  }
+
+  //Play out a game starting with first and return its outcome
+  pub fn rollout(&self, board : &Board, first : Action, rng : &mut XorShiftRng) -> f32 {
+    let (after, mut score) = board.move_and_score(first);
+    let mut board = after.add_random_with(rng);
+    let mut moves = 1;
+    let mut alive = true;
+    loop {
+      match self.max_depth {
+        Some(depth) if moves >= depth => break,
+        _ => ()
+      }
+      let actions = board.get_actions();
+      if actions.len() == 0 {
+        alive = false;
+        break
+      }
+      let action = self.pick(&board, &actions, rng);
+      let (after, reward) = board.move_and_score(action);
+      score += reward;
+      board = after.add_random_with(rng);
+      moves += 1;
+    }
+    match self.outcome {
+      RolloutScore => score as f32,
+      RolloutMaxTile => board.get_best_tile() as f32,
+      RolloutSurvival => if alive { 1. } else { 0. }
+    }
+  }
+
+  //Mean outcome of every legal action
+  pub fn action_values(&self, board : &Board) -> Vec<(Action, f32)> {
+    self.values_with(board, &mut random_rng())
+  }
+
+  fn values_with(&self, board : &Board, rng : &mut XorShiftRng) -> Vec<(Action, f32)> {
+    let actions = board.get_actions();
+    let (tx, rx) : (Sender<Vec<f32>>, Receiver<Vec<f32>>) = channel();
+    for thread in range(0, self.threads) {
+      let n = self.rollouts / self.threads +
+        if thread < self.rollouts % self.threads { 1 } else { 0 };
+      let me = self.clone();
+      let board = board.clone();
+      let actions = actions.clone();
+      let tx = tx.clone();
+      let mut thread_rng : XorShiftRng = rng.gen();
+      spawn(proc() {
+        let totals = actions.iter().map(|&action| {
+          range(0, n).fold(0., |total, _| total + me.rollout(&board, action, &mut thread_rng))
+        }).collect();
+        tx.send(totals);
+      });
+    }
+    let mut totals = Vec::from_elem(actions.len(), 0f32);
+    for _ in range(0, self.threads) {
+      for (total, part) in totals.mut_iter().zip(rx.recv().iter()) {
+        *total += *part;
+      }
+    }
+    actions.iter().zip(totals.iter()).map(|(&action, &total)| {
+      (action, total / self.rollouts as f32)
+    }).collect()
+  }
+}
+
+impl AIPlayer for MonteCarlo {
+  fn reseed(&mut self, seed : u32, game : uint) {
+    self.rng = seeded_rng(seed, game);
+  }
+
+  fn next_action(&mut self, board : &Board) -> Action {
+    let inf : f32 = Float::infinity();
+    let mut best_value = -inf;
+    let mut best = None;
+    for &(action, value) in self.action_scores(board).unwrap().iter() {
+      if value > best_value {
+        best_value = value;
+        best = Some(action);
+      }
+    }
+    best.unwrap()
+  }
+
+  fn action_scores(&mut self, board : &Board) -> Option<Vec<(Action, f32)>> {
+    let mut rng = self.rng.clone();
+    let values = self.values_with(board, &mut rng);
+    self.rng = rng;
+    Some(values)
+  }
+}
+
+#[test]
+pub fn test_MonteCarlo_depth_limit() {
+  let mut board = Board::empty();
+  board.vec  = vec!(0, 2, 4, 2,
+                    0, 2, 0, 2,
+                    0, 4, 4, 2,
+                    0, 4, 2, 2);
+  let m = MonteCarlo::new(4, Some(1), RandomRollout, RolloutMaxTile, 2);
+  let values = m.action_values(&board);
+  assert_eq!(values.len(), board.get_actions().len());
+  for &(action, value) in values.iter() {
+    //a single move can never build anything larger than one merge
+    assert!(value <= 8.);
+    assert!(value >= board.move(action).get_best_tile() as f32);
+  }
+  //no rollouts asked for still plays one
+  let mut m = MonteCarlo::new(0, Some(1), RandomRollout, RolloutMaxTile, 2);
+  assert!(board.get_actions().contains(&m.next_action(&board)));
+}
@@ -0,0 +1,222 @@
+use std::io::{File, IoResult, IoError, BufferedReader, BufferedWriter, InvalidInput};
+use std::num::Float;
+use std::sync::Arc;
+use rand::{Rng, task_rng};
+
+use game::{Action, Board};
+use ai::{AIPlayer, Evaluator};
+use env::{OneHotPlanes, Planes, action_index, encode};
+
+static FILE_VERSION : u32 = 1;
+//One-hot planes of a 4x4 board
+static INPUTS : uint = 256;
+//A logit for each of env::ACTIONS then the value of the position
+static OUTPUTS : uint = 5;
+
+//Fully connected layer, weights hold one row of inputs per output
+#[deriving(Clone)]
+pub struct Layer {
+  inputs : uint,
+  outputs : uint,
+  weights : Vec<f32>,
+  biases : Vec<f32>
+}
+
+impl Layer {
+  //Uniform in +-1/sqrt(inputs)
+  pub fn random(inputs : uint, outputs : uint) -> Layer {
+    let mut rng = task_rng();
+    let scale = 1. / (inputs as f32).sqrt();
+    Layer {
+      inputs : inputs,
+      outputs : outputs,
+      weights : Vec::from_fn(inputs * outputs, |_| rng.gen_range(-scale, scale)),
+      biases : Vec::from_elem(outputs, 0f32)
+    }
+  }
+
+  //x holds batch rows of inputs, the result batch rows of outputs
+  fn forward(&self, x : &Vec<f32>, batch : uint, relu : bool) -> Vec<f32> {
+    let mut y = Vec::from_elem(batch * self.outputs, 0f32);
+    for o in range(0, self.outputs) {
+      let row = self.weights.slice(o * self.inputs, (o + 1) * self.inputs);
+      for b in range(0, batch) {
+        let input = x.slice(b * self.inputs, (b + 1) * self.inputs);
+        let mut sum = *self.biases.get(o);
+        for (w, v) in row.iter().zip(input.iter()) {
+          sum += *w * *v;
+        }
+        *y.get_mut(b * self.outputs + o) = if relu && sum < 0. { 0. } else { sum };
+      }
+    }
+    y
+  }
+}
+
+fn bad_file(desc : &'static str) -> IoError {
+  IoError { kind : InvalidInput, desc : desc, detail : None }
+}
+
+//Small feed forward network on the one-hot tile planes of a 4x4 board with
+//ReLU between layers. It gives both a policy and a value so the same weights
+//work as a player and as an evaluator inside a search.
+#[deriving(Clone)]
+pub struct Network {
+  layers : Vec<Layer>
+}
+
+impl Network {
+  //Randomly initialised, with the given hidden layer sizes
+  pub fn random(hidden : &Vec<uint>) -> Network {
+    let mut sizes = vec!(INPUTS);
+    sizes.push_all(hidden.as_slice());
+    sizes.push(OUTPUTS);
+    let layers = sizes.as_slice().windows(2).map(|w| Layer::random(w[0], w[1])).collect();
+    Network { layers : layers }
+  }
+
+  //Outputs for every board, each a logit per action then the value
+  pub fn forward_batch(&self, boards : &Vec<Board>) -> Vec<Vec<f32>> {
+    let mut x = Vec::with_capacity(boards.len() * INPUTS);
+    for board in boards.iter() {
+      match encode(board, OneHotPlanes) {
+        Planes(planes) => x.push_all_move(planes),
+        _ => unreachable!()
+      }
+    }
+    let last = self.layers.len() - 1;
+    for (i, layer) in self.layers.iter().enumerate() {
+      x = layer.forward(&x, boards.len(), i != last);
+    }
+    x.as_slice().chunks(OUTPUTS).map(|out| Vec::from_slice(out)).collect()
+  }
+
+  pub fn forward(&self, board : &Board) -> Vec<f32> {
+    self.forward_batch(&vec!(board.clone())).move_iter().next().unwrap()
+  }
+
+  //Format, all little endian:
+  //  "NNET", version u32, layer count u32
+  //  for each layer: inputs u32, outputs u32, outputs * inputs f32 weights
+  //  with all the weights of the first output first, then outputs f32 biases
+  //The first layer takes the 256 one-hot inputs, plane e cell i at e*16 + i
+  //where cells are in the order of Board::vec, and the last layer has 5 outputs.
+  pub fn save(&self, path : &Path) -> IoResult<()> {
+    let mut w = BufferedWriter::new(try!(File::create(path)));
+    try!(w.write(b"NNET"));
+    try!(w.write_le_u32(FILE_VERSION));
+    try!(w.write_le_u32(self.layers.len() as u32));
+    for layer in self.layers.iter() {
+      try!(w.write_le_u32(layer.inputs as u32));
+      try!(w.write_le_u32(layer.outputs as u32));
+      for &weight in layer.weights.iter().chain(layer.biases.iter()) {
+        try!(w.write_le_f32(weight));
+      }
+    }
+    w.flush()
+  }
+
+  pub fn load(path : &Path) -> IoResult<Network> {
+    let mut r = BufferedReader::new(try!(File::open(path)));
+    let magic = try!(r.read_exact(4));
+    if magic.as_slice() != b"NNET" {
+      return Err(bad_file("not a network weight file"))
+    }
+    if try!(r.read_le_u32()) != FILE_VERSION {
+      return Err(bad_file("unknown network file version"))
+    }
+    let num_layers = try!(r.read_le_u32()) as uint;
+    let mut layers = vec!();
+    let mut expected = INPUTS;
+    for _ in range(0, num_layers) {
+      let inputs = try!(r.read_le_u32()) as uint;
+      let outputs = try!(r.read_le_u32()) as uint;
+      if inputs != expected {
+        return Err(bad_file("layer sizes do not line up"))
+      }
+      let mut weights = Vec::with_capacity(inputs * outputs);
+      for _ in range(0, inputs * outputs) {
+        weights.push(try!(r.read_le_f32()));
+      }
+      let mut biases = Vec::with_capacity(outputs);
+      for _ in range(0, outputs) {
+        biases.push(try!(r.read_le_f32()));
+      }
+      layers.push(Layer { inputs : inputs, outputs : outputs, weights : weights, biases : biases });
+      expected = outputs;
+    }
+    if num_layers == 0 || expected != OUTPUTS {
+      return Err(bad_file("network must end with 5 outputs"))
+    }
+    Ok(Network { layers : layers })
+  }
+}
+
+//The value output, so a network can replace herustic
+impl Evaluator for Network {
+  fn evaluate(&self, board : &Board) -> f32 {
+    *self.forward(board).get(OUTPUTS - 1)
+  }
+
+  fn evaluate_batch(&self, boards : &Vec<Board>) -> Vec<f32> {
+    self.forward_batch(boards).iter().map(|out| *out.get(OUTPUTS - 1)).collect()
+  }
+}
+
+//Plays the legal action with the highest policy logit, no search
+#[deriving(Clone)]
+pub struct PolicyPlayer {
+  network : Arc<Network>
+}
+
+impl PolicyPlayer {
+  pub fn new(network : Arc<Network>) -> PolicyPlayer {
+    PolicyPlayer { network : network }
+  }
+}
+
+impl AIPlayer for PolicyPlayer {
+  fn next_action(&mut self, board : &Board) -> Action {
+    let out = self.network.forward(board);
+    let inf : f32 = Float::infinity();
+    let mut best_logit = -inf;
+    let mut best = None;
+    for &action in board.get_actions().iter() {
+      let logit = *out.get(action_index(action));
+      if best.is_none() || logit > best_logit {
+        best_logit = logit;
+        best = Some(action);
+      }
+    }
+    best.unwrap()
+  }
+
+  fn action_scores(&mut self, board : &Board) -> Option<Vec<(Action, f32)>> {
+    let out = self.network.forward(board);
+    Some(board.get_actions().iter().map(|&a| (a, *out.get(action_index(a)))).collect())
+  }
+}
+
+#[test]
+pub fn test_Network_batch_and_file() {
+  use std::io::fs;
+  use env::NUM_PLANES;
+  assert_eq!(NUM_PLANES * 16, INPUTS);
+  let network = Network::random(&vec!(32, 16));
+  let boards = vec!(Board::new(), Board::new(), Board::new());
+  let batch = network.forward_batch(&boards);
+  for (board, out) in boards.iter().zip(batch.iter()) {
+    assert_eq!(*out, network.forward(board));
+  }
+
+  let path = Path::new("test_network.bin");
+  network.save(&path).unwrap();
+  let loaded = Network::load(&path).unwrap();
+  fs::unlink(&path).unwrap();
+  assert_eq!(loaded.evaluate_batch(&boards), network.evaluate_batch(&boards));
+
+  let mut player = PolicyPlayer::new(Arc::new(loaded));
+  for board in boards.iter() {
+    assert!(board.get_actions().contains(&player.next_action(board)));
+  }
+}
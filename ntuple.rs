@@ -0,0 +1,256 @@
+use std::io::{File, IoResult, IoError, BufferedReader, BufferedWriter, InvalidInput};
+use std::iter::FromIterator;
+use std::num::{Float, pow};
+use std::sync::Arc;
+
+use game::{Action, Board, Cord, exponent};
+use ai::{AIPlayer, Evaluator};
+
+//Tiles up to 32768 get their own weight, anything larger shares the last one
+static NUM_RANKS : uint = 16;
+//Longest tuple load accepts, 16^7 weights is already a gigabyte
+static MAX_TUPLE_LEN : uint = 7;
+static FILE_VERSION : u32 = 1;
+
+//Which of the NUM_RANKS weights a tile uses
+fn tile_rank(value : int) -> uint {
+  let e = exponent(value);
+  if e >= NUM_RANKS { NUM_RANKS - 1 } else { e }
+}
+
+//Cells are indexed the same way as Board::vec
+fn transform_index(index : uint, sym : uint) -> uint {
+  let Cord(x, y) = Cord((index % 4) as int, (index / 4) as int).transform(sym);
+  (x + y*4) as uint
+}
+
+//The four 6-tuples from Szubert & Jaskowski, two straight and two boxy
+pub fn default_tuples() -> Vec<Vec<uint>> {
+  vec!(vec!(0, 1, 2, 3, 4, 5),
+       vec!(4, 5, 6, 7, 8, 9),
+       vec!(0, 1, 2, 4, 5, 6),
+       vec!(4, 5, 6, 8, 9, 10))
+}
+
+#[deriving(Clone)]
+pub struct NTupleNetwork {
+  tuples : Vec<Vec<uint>>,
+  //Every tuple under all 8 symmetries, paired with the weights it uses
+  expanded : Vec<(uint, Vec<uint>)>,
+  weights : Vec<Vec<f32>>
+}
+
+impl NTupleNetwork {
+  pub fn new(tuples : Vec<Vec<uint>>) -> NTupleNetwork {
+    let weights = FromIterator::from_iter(tuples.iter().map(|tuple| {
+      Vec::from_elem(pow(NUM_RANKS, tuple.len()), 0f32)
+    }));
+    let mut expanded = vec!();
+    for (t, tuple) in tuples.iter().enumerate() {
+      for sym in range(0u, 8) {
+        let cells = FromIterator::from_iter(
+          tuple.iter().map(|&index| transform_index(index, sym)));
+        expanded.push((t, cells));
+      }
+    }
+    NTupleNetwork { tuples : tuples, expanded : expanded, weights : weights }
+  }
+
+  fn index(cells : &Vec<uint>, board : &Board) -> uint {
+    let mut index = 0;
+    for &cell in cells.iter() {
+      index = index * NUM_RANKS + tile_rank(*board.vec.get(cell));
+    }
+    index
+  }
+
+  //Adds amount to every weight used to evaluate board
+  pub fn update(&mut self, board : &Board, amount : f32) {
+    let per_feature = amount / self.expanded.len() as f32;
+    for &(t, ref cells) in self.expanded.iter() {
+      let index = NTupleNetwork::index(cells, board);
+      *self.weights.get_mut(t).get_mut(index) += per_feature;
+    }
+  }
+
+  //Format, all little endian:
+  //  "NTUP", version u32, tuple count u32
+  //  for each tuple: length u32, then one u8 cell index per entry
+  //  for each tuple: 16^length f32 weights
+  pub fn save(&self, path : &Path) -> IoResult<()> {
+    let mut w = BufferedWriter::new(try!(File::create(path)));
+    try!(w.write(b"NTUP"));
+    try!(w.write_le_u32(FILE_VERSION));
+    try!(w.write_le_u32(self.tuples.len() as u32));
+    for tuple in self.tuples.iter() {
+      try!(w.write_le_u32(tuple.len() as u32));
+      for &cell in tuple.iter() {
+        try!(w.write_u8(cell as u8));
+      }
+    }
+    for weights in self.weights.iter() {
+      for &weight in weights.iter() {
+        try!(w.write_le_f32(weight));
+      }
+    }
+    w.flush()
+  }
+
+  pub fn load(path : &Path) -> IoResult<NTupleNetwork> {
+    let mut r = BufferedReader::new(try!(File::open(path)));
+    let magic = try!(r.read_exact(4));
+    if magic.as_slice() != b"NTUP" {
+      return Err(IoError { kind : InvalidInput, desc : "not an n-tuple weight file", detail : None });
+    }
+    if try!(r.read_le_u32()) != FILE_VERSION {
+      return Err(IoError { kind : InvalidInput, desc : "unknown weight file version", detail : None });
+    }
+    let num_tuples = try!(r.read_le_u32()) as uint;
+    let mut tuples = vec!();
+    for _ in range(0, num_tuples) {
+      let len = try!(r.read_le_u32()) as uint;
+      if len > MAX_TUPLE_LEN {
+        return Err(IoError { kind : InvalidInput, desc : "n-tuple too long", detail : None });
+      }
+      let mut tuple = vec!();
+      for _ in range(0, len) {
+        let cell = try!(r.read_u8()) as uint;
+        if cell >= 16 {
+          return Err(IoError { kind : InvalidInput, desc : "n-tuple cell off the board", detail : None });
+        }
+        tuple.push(cell);
+      }
+      tuples.push(tuple);
+    }
+    let mut network = NTupleNetwork::new(tuples);
+    for weights in network.weights.mut_iter() {
+      for weight in weights.mut_iter() {
+        *weight = try!(r.read_le_f32());
+      }
+    }
+    Ok(network)
+  }
+
+  //Picks the action with the best reward plus afterstate value,
+  //returns the action, the afterstate and the reward
+  pub fn best_move(&self, board : &Board) -> Option<(Action, Board, int)> {
+    let inf : f32 = Float::infinity();
+    let mut best_value = -inf;
+    let mut best = None;
+    for &action in board.get_actions().iter() {
+      let (after, reward) = board.move_and_score(action);
+      let value = reward as f32 + self.evaluate(&after);
+      if value > best_value {
+        best_value = value;
+        best = Some((action, after, reward));
+      }
+    }
+    best
+  }
+
+  //Plays one game greedily and learns from it with TD(lambda) on afterstates,
+  //lambda of 0 is plain TD(0). Returns the final board and the game score.
+  pub fn learn_game(&mut self, alpha : f32, lambda : f32) -> (Board, int) {
+    let mut board = Board::new();
+    let mut score = 0;
+    let mut afterstates = vec!();
+    let mut rewards = vec!();
+    loop {
+      match self.best_move(&board) {
+        None => break,
+        Some((_, after, reward)) => {
+          score += reward;
+          board = after.add_random();
+          afterstates.push(after);
+          rewards.push(reward as f32);
+        }
+      }
+    }
+
+    //Walk the game backwards building the lambda-return of each afterstate
+    //from the reward of the move that left it and the next afterstate's value.
+    //Nothing is earned after the last afterstate so its target is 0.
+    let mut target = 0.;
+    let mut t = afterstates.len();
+    while t > 0 {
+      t -= 1;
+      let value = self.evaluate(afterstates.get(t));
+      self.update(afterstates.get(t), alpha * (target - value));
+      target = *rewards.get(t) + (1. - lambda) * value + lambda * target;
+    }
+    (board, score)
+  }
+}
+
+impl Evaluator for NTupleNetwork {
+  fn evaluate(&self, board : &Board) -> f32 {
+    let mut total = 0.;
+    for &(t, ref cells) in self.expanded.iter() {
+      total += *self.weights.get(t).get(NTupleNetwork::index(cells, board));
+    }
+    total
+  }
+}
+
+//Greedy one ply player using a trained network
+#[deriving(Clone)]
+pub struct NTuplePlayer {
+  network : Arc<NTupleNetwork>
+}
+
+impl NTuplePlayer {
+  pub fn new(network : Arc<NTupleNetwork>) -> NTuplePlayer {
+    NTuplePlayer { network : network }
+  }
+}
+
+impl AIPlayer for NTuplePlayer {
+  fn next_action(&mut self, board : &Board) -> Action {
+    let (action, _, _) = self.network.best_move(board).unwrap();
+    action
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use super::{NTupleNetwork, default_tuples, tile_rank};
+  use game::Board;
+  use ai::Evaluator;
+
+  #[test]
+  fn test_tile_rank() {
+    assert_eq!(tile_rank(0), 0);
+    assert_eq!(tile_rank(2), 1);
+    assert_eq!(tile_rank(2048), 11);
+    assert_eq!(tile_rank(131072), 15);
+  }
+
+  #[test]
+  fn test_update_symmetric() {
+    let mut network = NTupleNetwork::new(default_tuples());
+    let mut board = Board::empty();
+    board.vec = vec!(2, 4, 0, 0,
+                     0, 8, 0, 0,
+                     0, 0, 0, 0,
+                     0, 0, 0, 0);
+    network.update(&board, 1.);
+    let value = network.evaluate(&board);
+    assert!(value > 0.);
+    let mut mirrored = Board::empty();
+    mirrored.vec = vec!(0, 0, 4, 2,
+                        0, 0, 8, 0,
+                        0, 0, 0, 0,
+                        0, 0, 0, 0);
+    assert!((network.evaluate(&mirrored) - value).abs() < 1e-4);
+  }
+
+  #[test]
+  fn test_load_rejects_bad_cells() {
+    use std::io::fs;
+    let path = Path::new("test_bad_tuple.ntup");
+    NTupleNetwork::new(vec!(vec!(0, 20))).save(&path).unwrap();
+    let loaded = NTupleNetwork::load(&path);
+    fs::unlink(&path).unwrap();
+    assert!(loaded.is_err());
+  }
+}
@@ -0,0 +1,71 @@
+extern crate rand;
+
+use std::os;
+use std::num::Float;
+use ai::{ExpectiMax, Heuristic};
+use exact::ExactExpectiMax;
+use record::GameRecord;
+
+pub mod game;
+pub mod ai;
+pub mod minimax;
+pub mod spawn;
+pub mod record;
+pub mod pool;
+pub mod stats;
+pub mod exact;
+
+static DEPTH : uint = 4;
+static NUM_EXPECTI : uint = 6;
+//Only every STRIDE-th position of each game goes into the suite
+static STRIDE : uint = 10;
+
+//Measure the error the num_expecti sampling in ExpectiMax introduces by
+//comparing its choices against the exact search on a suite of positions
+//taken from recorded games.
+//usage: oracle <record>...
+pub fn main() {
+  let args = os::args();
+  if args.len() < 2 {
+    println!("usage: oracle <record>...");
+    return
+  }
+  let sampled = ExpectiMax::new(DEPTH, NUM_EXPECTI, Heuristic::default());
+  let mut exact = ExactExpectiMax::new(DEPTH, Heuristic::default());
+
+  let mut positions = 0u;
+  let mut agree = 0u;
+  let mut total_loss = 0.;
+  let mut worst_loss = 0.;
+  let mut total_error = 0.;
+  for path in args.iter().skip(1) {
+    let record = GameRecord::load(&Path::new(path.as_slice())).unwrap();
+    for (i, board) in record.boards.iter().enumerate() {
+      if i % STRIDE != 0 || board.get_actions().len() < 2 {
+        continue
+      }
+      let analysis = sampled.analyse(board);
+      let values = exact.action_values(board);
+      let inf : f32 = Float::infinity();
+      let best = values.iter().fold(-inf, |m, &(_, v)| m.max(v));
+      let choice = analysis.best().action;
+      let &(_, chosen) = values.iter().find(|&&(a, _)| a == choice).unwrap();
+
+      positions += 1;
+      if chosen == best {
+        agree += 1;
+      }
+      let loss = best - chosen;
+      total_loss += loss;
+      if loss > worst_loss {
+        worst_loss = loss;
+      }
+      //how far the sampled estimate of the chosen move is from the truth
+      total_error += (analysis.best().value - chosen).abs();
+    }
+  }
+  println!("{} positions, depth {}, {} samples per chance node", positions, DEPTH, NUM_EXPECTI);
+  println!("Same move as exact: {}", agree as f32 / positions as f32);
+  println!("Mean value lost: {}, worst {}", total_loss / positions as f32, worst_loss);
+  println!("Mean error of the sampled value: {}", total_error / positions as f32);
+}
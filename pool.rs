@@ -0,0 +1,90 @@
+use std::fmt;
+use std::os;
+use std::sync::{Arc, Mutex};
+use std::task;
+
+//A fixed set of tasks that run queued jobs in the order they were queued.
+//The tasks stay alive for as long as the pool, so it can be given any number
+//of jobs over its lifetime. Each job runs in a child task of its worker, so a
+//job that fails does not take the worker with it.
+pub struct WorkerPool {
+  threads : uint,
+  jobs : Sender<proc():Send>
+}
+
+impl WorkerPool {
+  pub fn new(threads : uint) -> WorkerPool {
+    let threads = if threads == 0 { 1 } else { threads };
+    let (tx, rx) : (Sender<proc():Send>, Receiver<proc():Send>) = channel();
+    let rx = Arc::new(Mutex::new(rx));
+    for _ in range(0, threads) {
+      let rx = rx.clone();
+      spawn(proc() {
+        loop {
+          //the lock is only held while waiting, not while the job runs
+          let job = match rx.lock().recv_opt() {
+            Ok(job) => job,
+            Err(()) => break
+          };
+          //a failed job has already printed why, the worker goes on to the next one
+          let _ = task::try(job);
+        }
+      });
+    }
+    WorkerPool { threads : threads, jobs : tx }
+  }
+
+  //One task per CPU
+  pub fn default() -> WorkerPool {
+    WorkerPool::new(os::num_cpus())
+  }
+
+  pub fn threads(&self) -> uint {
+    self.threads
+  }
+
+  pub fn execute(&self, job : proc():Send) {
+    self.jobs.send(job);
+  }
+
+  //Runs job and sends its result on done along with id, or None if the job
+  //failed, so the caller waiting on done hears about every job either way
+  pub fn run<T : Send>(&self, id : uint, job : proc():Send -> T, done : &Sender<(uint, Option<T>)>) {
+    let done = done.clone();
+    self.execute(proc() {
+      let _ = done.send_opt((id, task::try(job).ok()));
+    });
+  }
+}
+
+impl fmt::Show for WorkerPool {
+  fn fmt(&self, f : &mut fmt::Formatter) -> fmt::Result {
+    write!(f, "WorkerPool of {} threads", self.threads)
+  }
+}
+
+#[test]
+pub fn test_WorkerPool_runs_every_job() {
+  let pool = WorkerPool::new(3);
+  let (tx, rx) = channel();
+  for i in range(0u, 10) {
+    let tx = tx.clone();
+    pool.execute(proc() {
+      tx.send(i * i);
+    });
+  }
+  let mut results : Vec<uint> = range(0u, 10).map(|_| rx.recv()).collect();
+  results.sort();
+  assert_eq!(results, range(0u, 10).map(|i| i * i).collect());
+}
+
+#[test]
+pub fn test_WorkerPool_survives_failed_jobs() {
+  let pool = WorkerPool::new(1);
+  let (tx, rx) = channel();
+  pool.run(0, proc() -> uint { fail!("job 0") }, &tx);
+  pool.run(1, proc() 1u, &tx);
+  assert_eq!(rx.recv(), (0, None));
+  //the only worker is still there to run the next job
+  assert_eq!(rx.recv(), (1, Some(1)));
+}
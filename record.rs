@@ -0,0 +1,136 @@
+use std::io::{BufferedReader, File, IoResult, IoError, InvalidInput};
+
+use game::{Action, Board, Up, Down, Left, Right};
+
+pub fn letter(action : Action) -> &'static str {
+  match action {
+    Up => "w",
+    Left => "a",
+    Down => "s",
+    Right => "d"
+  }
+}
+
+pub fn from_letter(letter : &str) -> Option<Action> {
+  match letter {
+    "w" => Some(Up),
+    "a" => Some(Left),
+    "s" => Some(Down),
+    "d" => Some(Right),
+    _ => None
+  }
+}
+
+fn bad_line(line : &str) -> IoError {
+  IoError { kind : InvalidInput, desc : "bad game record line", detail : Some(line.to_owned()) }
+}
+
+//Every position of a game and the move played from it.
+//There is one more board than actions, the last one is where the game ended.
+#[deriving(Show, Clone)]
+pub struct GameRecord {
+  pub boards : Vec<Board>,
+  pub actions : Vec<Action>
+}
+
+impl GameRecord {
+  pub fn new() -> GameRecord {
+    GameRecord { boards : vec!(), actions : vec!() }
+  }
+
+  pub fn push(&mut self, board : &Board, action : Action) {
+    self.boards.push(board.clone());
+    self.actions.push(action);
+  }
+
+  pub fn finish(&mut self, board : &Board) {
+    self.boards.push(board.clone());
+  }
+
+  //A width and height line, then one line per position: its tiles in the
+  //order of Board::vec followed by the move played as a wasd letter, or -
+  //for the final position. Records without the size line are 4x4.
+  pub fn save(&self, path : &Path) -> IoResult<()> {
+    let mut file = try!(File::create(path));
+    let (width, height) = match self.boards.as_slice().get(0) {
+      Some(board) => (board.width, board.height),
+      None => (4, 4)
+    };
+    try!(writeln!(file, "{} {}", width, height));
+    for (i, board) in self.boards.iter().enumerate() {
+      let tiles : Vec<String> = board.vec.iter().map(|t| t.to_str()).collect();
+      let action = if i < self.actions.len() { letter(*self.actions.get(i)) } else { "-" };
+      try!(writeln!(file, "{} {}", tiles.connect(" "), action));
+    }
+    Ok(())
+  }
+
+  pub fn load(path : &Path) -> IoResult<GameRecord> {
+    let mut reader = BufferedReader::new(try!(File::open(path)));
+    let mut record = GameRecord::new();
+    let (mut width, mut height) = (4, 4);
+    for line in reader.lines() {
+      let line = try!(line);
+      let words : Vec<&str> = line.as_slice().words().collect();
+      if words.len() == 0 {
+        continue
+      }
+      if words.len() == 2 && record.boards.len() == 0 {
+        match (from_str::<int>(*words.get(0)), from_str::<int>(*words.get(1))) {
+          (Some(w), Some(h)) if w > 0 && h > 0 => {
+            width = w;
+            height = h;
+            continue
+          }
+          _ => return Err(bad_line(line.as_slice()))
+        }
+      }
+      let cells = (width * height) as uint;
+      if words.len() != cells + 1 {
+        return Err(bad_line(line.as_slice()))
+      }
+      let mut board = Board::empty_sized(width, height);
+      for (i, word) in words.iter().take(cells).enumerate() {
+        match from_str::<int>(*word) {
+          Some(tile) => *board.vec.get_mut(i) = tile,
+          None => return Err(bad_line(line.as_slice()))
+        }
+      }
+      match from_letter(*words.get(cells)) {
+        Some(action) => record.push(&board, action),
+        None => record.finish(&board)
+      }
+    }
+    Ok(record)
+  }
+}
+
+#[test]
+pub fn test_GameRecord_round_trip() {
+  use std::io::fs;
+  let mut record = GameRecord::new();
+  let board = Board::new();
+  record.push(&board, Left);
+  record.push(&board.move(Left), Down);
+  record.finish(&board.move(Left).move(Down));
+  let path = Path::new("test_record.txt");
+  record.save(&path).unwrap();
+  let loaded = GameRecord::load(&path).unwrap();
+  fs::unlink(&path).unwrap();
+  assert_eq!(loaded.actions, record.actions);
+  assert_eq!(loaded.boards, record.boards);
+}
+
+#[test]
+pub fn test_GameRecord_small_board() {
+  use std::io::fs;
+  let mut record = GameRecord::new();
+  let board = Board::new_sized(3, 2);
+  record.push(&board, Left);
+  record.finish(&board.move(Left));
+  let path = Path::new("test_record_small.txt");
+  record.save(&path).unwrap();
+  let loaded = GameRecord::load(&path).unwrap();
+  fs::unlink(&path).unwrap();
+  assert_eq!(loaded.boards, record.boards);
+}
@@ -1,36 +1,134 @@
 extern crate rand;
 
-use game::{Board, Left, Right, Up, Down};
+use std::os;
+use std::cmp::{Less, Greater, Equal};
+use game::{Action, Board, Left, Right, Up, Down};
+use ai::{ExpectiMax, Heuristic};
+use spawn::{SpawnPolicy, RandomSpawn, AdversarialSpawn, HelpfulSpawn, ScriptedSpawn};
 
 pub mod game;
+pub mod ai;
+pub mod minimax;
+pub mod spawn;
+pub mod record;
+pub mod pool;
+pub mod stats;
 
-fn main() {
-  println!("Enter wasd");
-  let mut board = Board::new();
+//Expected value lost compared to the best move before a move is flagged
+static INACCURACY : f32 = 2.;
+static BLUNDER : f32 = 8.;
+//How many of the worst moves to show at the end of the game
+static WORST_SHOWN : uint = 5;
+
+struct Decision {
+  move_number : uint,
+  board : Board,
+  chosen : Action,
+  best : Action,
+  loss : f32
+}
+
+fn grade(loss : f32) -> &'static str {
+  if loss >= BLUNDER {
+    "blunder"
+  } else if loss >= INACCURACY {
+    "inaccuracy"
+  } else {
+    "good"
+  }
+}
+
+fn print_summary(decisions : &mut Vec<Decision>) {
+  decisions.sort_by(|a, b| if a.loss > b.loss { Less } else if a.loss < b.loss { Greater } else { Equal });
+  let blunders = decisions.iter().filter(|d| d.loss >= BLUNDER).count();
+  let inaccuracies = decisions.iter().filter(|d| d.loss >= INACCURACY && d.loss < BLUNDER).count();
+  println!("{} graded moves, {} inaccuracies, {} blunders", decisions.len(), inaccuracies, blunders);
+  for d in decisions.iter().take(WORST_SHOWN).filter(|d| d.loss >= INACCURACY) {
+    println!("Move {}: played {} instead of {}, lost {:.2} ({}){}",
+             d.move_number, d.chosen, d.best, d.loss, grade(d.loss), d.board);
+  }
+}
+
+fn play<S : SpawnPolicy>(spawner : &mut S) {
+  let expecti = ExpectiMax::new(4, 8, Heuristic::default());
+  let mut grading = false;
+  let mut decisions = vec!();
+  let mut moves = 0;
+  println!("Enter wasd, h for a hint, g to toggle grading your moves");
+  let mut board = spawner.start();
   println!("{}", board);
   for line in std::io::stdin().lines() {
     let string = match line {
       Ok(s) => s,
       _ => "nothing".to_owned()
     };
-    let mut trial_board;
+    let action;
     if string == "a\n".to_owned() {
-      trial_board = board.move(Left);
+      action = Left;
     } else if string == "d\n".to_owned() {
-      trial_board = board.move(Right);
+      action = Right;
     } else if string == "w\n".to_owned() {
-      trial_board = board.move(Up);
+      action = Up;
     } else if string == "s\n".to_owned() {
-      trial_board = board.move(Down);
+      action = Down;
+    } else if string == "h\n".to_owned() {
+      let analysis = expecti.analyse(&board);
+      println!("Hint: {}, ahead of the next best by {:.2}", analysis.best().action, analysis.margin());
+      continue
+    } else if string == "g\n".to_owned() {
+      grading = !grading;
+      println!("Grading {}", if grading { "on" } else { "off" });
+      continue
     } else {
-      println!("Enter wasd");
+      println!("Enter wasd, h for a hint, g to toggle grading your moves");
       continue
     }
+    let trial_board = board.move(action);
     if board == trial_board {
       println!("{}", board);
       continue
     }
-    board = trial_board.add_random();
+    moves += 1;
+    if grading {
+      let analysis = expecti.analyse(&board);
+      let best = analysis.best();
+      let loss = best.value - analysis.get(action).unwrap().value;
+      let loss = if loss < 0. { 0. } else { loss };
+      if loss >= INACCURACY {
+        println!("{}: {} was better by {:.2}", grade(loss), best.action, loss);
+      }
+      decisions.push(Decision {
+        move_number : moves,
+        board : board.clone(),
+        chosen : action,
+        best : best.action,
+        loss : loss
+      });
+    }
+    board = spawner.spawn(&trial_board);
     println!("{}", board);
+    if board.get_actions().len() == 0 {
+      println!("Game over after {} moves, {}", moves, board.summary());
+      if decisions.len() > 0 {
+        print_summary(&mut decisions);
+      }
+      break
+    }
+  }
+}
+
+//usage: repl [random | evil | helpful | script <file>]
+fn main() {
+  let args = os::args();
+  let spawn = if args.len() > 1 { args.get(1).as_slice() } else { "random" };
+  match spawn {
+    "random" => play(&mut RandomSpawn),
+    "evil" => play(&mut AdversarialSpawn::new(2, Heuristic::default())),
+    "helpful" => play(&mut HelpfulSpawn::new(2, Heuristic::default())),
+    "script" => {
+      let path = args.as_slice().get(2).expect("repl script needs <file>");
+      play(&mut ScriptedSpawn::load(&Path::new(path.as_slice())).unwrap())
+    }
+    _ => println!("unknown spawn policy {}", spawn)
   }
 }
@@ -0,0 +1,101 @@
+extern crate rand;
+
+use std::os;
+use std::num::Float;
+use ai::{AIPlayer, ExpectiMax, Heuristic, RandomPlayer, GreedyScore, spawned};
+use game::Board;
+use montecarlo::{MonteCarlo, RandomRollout, RolloutScore};
+use exact::ExactExpectiMax;
+use beam::BeamSearch;
+use solver::{Solver, ReachTile, FinalScore, TablePlayer};
+
+pub mod game;
+pub mod ai;
+pub mod minimax;
+pub mod spawn;
+pub mod record;
+pub mod pool;
+pub mod stats;
+pub mod montecarlo;
+pub mod exact;
+pub mod beam;
+pub mod solver;
+
+//Play games on a small board and return the mean outcome together with the
+//mean value lost per move compared to the solved values
+fn validate<T : AIPlayer>(player : &mut T, solver : &mut Solver, width : int, height : int,
+                          games : uint) -> (f64, f64) {
+  let mut total = 0.;
+  let mut loss = 0.;
+  let mut moves = 0u;
+  for _ in range(0, games) {
+    player.new_game();
+    let mut board = Board::new_sized(width, height);
+    let mut score = 0;
+    while board.get_actions().len() > 0 && !solver.done(&board) {
+      let action = player.next_action(&board);
+      let values = solver.action_values(&board);
+      let inf : f64 = Float::infinity();
+      let best = values.iter().fold(-inf, |m, &(_, v)| m.max(v));
+      let &(_, chosen) = values.iter().find(|&&(a, _)| a == action).unwrap();
+      loss += best - chosen;
+      moves += 1;
+      let (after, reward) = board.move_and_score(action);
+      score += reward;
+      board = after.add_random();
+      player.observe(action, spawned(&after, &board), &board);
+    }
+    total += solver.outcome(&board, score);
+  }
+  (total / games as f64, if moves > 0 { loss / moves as f64 } else { 0. })
+}
+
+//Solve a small board exactly, save the policy table and check the players
+//against it. The target is a tile to reach or "score" for expected score.
+//usage: solve <width> <height> <target|score> [games] [table out]
+pub fn main() {
+  let args = os::args();
+  if args.len() < 4 {
+    println!("usage: solve <width> <height> <target|score> [games] [table out]");
+    return
+  }
+  let width : int = from_str(args.get(1).as_slice()).expect("bad width");
+  let height : int = from_str(args.get(2).as_slice()).expect("bad height");
+  let objective = match args.get(3).as_slice() {
+    "score" => FinalScore,
+    target => ReachTile(from_str(target).expect("bad target"))
+  };
+  let games : uint = if args.len() > 4 { from_str(args.get(4).as_slice()).expect("bad games") } else { 1000 };
+
+  let mut solver = Solver::new(objective.clone());
+  let value = solver.solve(width, height);
+  println!("{}x{} {}: exact value {} over {} positions", width, height, objective, value,
+           solver.positions());
+
+  let mut table = if args.len() > 5 {
+    let path = Path::new(args.get(5).as_slice());
+    solver.table().save(&path).unwrap();
+    TablePlayer::load(&path).unwrap()
+  } else {
+    solver.table()
+  };
+
+  let (outcome, loss) = validate(&mut table, &mut solver, width, height, games);
+  println!("table:       {} mean, {} lost per move", outcome, loss);
+  let (outcome, loss) = validate(&mut ExpectiMax::new(4, 6, Heuristic::default()), &mut solver, width, height,
+                                 games);
+  println!("expectimax:  {} mean, {} lost per move", outcome, loss);
+  let (outcome, loss) = validate(&mut ExactExpectiMax::new(4, Heuristic::default()), &mut solver, width, height,
+                                 games);
+  println!("exact:       {} mean, {} lost per move", outcome, loss);
+  let (outcome, loss) = validate(&mut BeamSearch::new(16, 6, Heuristic::default()), &mut solver, width, height,
+                                 games);
+  println!("beam:        {} mean, {} lost per move", outcome, loss);
+  let (outcome, loss) = validate(&mut MonteCarlo::new(100, None, RandomRollout, RolloutScore, 4),
+                                 &mut solver, width, height, games);
+  println!("montecarlo:  {} mean, {} lost per move", outcome, loss);
+  let (outcome, loss) = validate(&mut GreedyScore::new(), &mut solver, width, height, games);
+  println!("greedy:      {} mean, {} lost per move", outcome, loss);
+  let (outcome, loss) = validate(&mut RandomPlayer::new(), &mut solver, width, height, games);
+  println!("random:      {} mean, {} lost per move", outcome, loss);
+}
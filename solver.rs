@@ -0,0 +1,252 @@
+use std::collections::HashMap;
+use std::io::{BufferedReader, File, IoResult, IoError, InvalidInput};
+
+use game::{Action, Board};
+use ai::AIPlayer;
+use record::{letter, from_letter};
+
+//What the solver maximises
+#[deriving(Clone, Show)]
+pub enum Objective {
+  //Probability of building a tile at least this large
+  ReachTile(int),
+  //Expected score still to be made before the game ends
+  FinalScore
+}
+
+//(packed board, board width, board height), so the maps do not hold a Vec per position
+type Key = (u64, int, int);
+
+fn key(board : &Board) -> Option<Key> {
+  board.pack().map(|packed| (packed, board.width, board.height))
+}
+
+fn solvable_key(board : &Board) -> Key {
+  key(board).expect("board too large to solve")
+}
+
+//Solves a whole game exactly by visiting every position reachable under
+//optimal play. Only practical on small boards such as 2x2, 2x3 and 3x3.
+pub struct Solver {
+  objective : Objective,
+  values : HashMap<Key, f64>,
+  policy : HashMap<Key, Action>
+}
+
+impl Solver {
+  pub fn new(objective : Objective) -> Solver {
+    Solver { objective : objective, values : HashMap::new(), policy : HashMap::new() }
+  }
+
+  //True once the objective can not change any more
+  pub fn done(&self, board : &Board) -> bool {
+    match self.objective {
+      ReachTile(target) => board.get_best_tile() >= target,
+      FinalScore => false
+    }
+  }
+
+  //What a finished game with score is worth
+  pub fn outcome(&self, board : &Board, score : int) -> f64 {
+    match self.objective {
+      ReachTile(target) => if board.get_best_tile() >= target { 1. } else { 0. },
+      FinalScore => score as f64
+    }
+  }
+
+  //Exact value of board with the player to move
+  pub fn value(&mut self, board : &Board) -> f64 {
+    let key = solvable_key(board);
+    match self.values.find(&key) {
+      Some(&value) => return value,
+      None => ()
+    }
+    let value = if self.done(board) {
+      1.
+    } else {
+      let values = self.action_values(board);
+      let mut best = None;
+      let mut best_value = 0.;
+      for &(action, value) in values.iter() {
+        if best.is_none() || value > best_value {
+          best_value = value;
+          best = Some(action);
+        }
+      }
+      match best {
+        Some(action) => { self.policy.insert(key, action); }
+        None => ()
+      }
+      best_value
+    };
+    self.values.insert(key, value);
+    value
+  }
+
+  //Exact value of board just before a tile spawns
+  fn chance_value(&mut self, board : &Board) -> f64 {
+    let empty = board.get_empty();
+    let p = 1. / empty.len() as f64;
+    let mut total = 0.;
+    for &indx in empty.iter() {
+      total += 0.9 * p * self.value(&board.add_space((indx, 2, 0.)));
+      total += 0.1 * p * self.value(&board.add_space((indx, 4, 0.)));
+    }
+    total
+  }
+
+  //Exact value of every legal action from board
+  pub fn action_values(&mut self, board : &Board) -> Vec<(Action, f64)> {
+    board.get_actions().iter().map(|&action| {
+      let (after, reward) = board.move_and_score(action);
+      let reward = match self.objective {
+        FinalScore => reward as f64,
+        ReachTile(_) => 0.
+      };
+      (action, reward + self.chance_value(&after))
+    }).collect()
+  }
+
+  //Exact value of a new game, averaged over every two tile opening
+  pub fn solve(&mut self, width : int, height : int) -> f64 {
+    let empty = Board::empty_sized(width, height);
+    let cells = empty.vec.len();
+    let mut total = 0.;
+    for first in range(0, cells) {
+      for second in range(0, cells) {
+        if first == second {
+          continue
+        }
+        for &(a, pa) in [(2, 0.9), (4, 0.1)].iter() {
+          for &(b, pb) in [(2, 0.9), (4, 0.1)].iter() {
+            let board = empty.add_space((first, a, 0.)).add_space((second, b, 0.));
+            let p = pa * pb / (cells * (cells - 1)) as f64;
+            total += p * self.value(&board);
+          }
+        }
+      }
+    }
+    total
+  }
+
+  //Positions solved so far
+  pub fn positions(&self) -> uint {
+    self.values.len()
+  }
+
+  pub fn table(&self) -> TablePlayer {
+    TablePlayer { policy : self.policy.clone() }
+  }
+}
+
+//Plays the moves of a solved policy table. Positions the solver never had
+//to visit, such as those after the target tile, fall back to the first legal move.
+#[deriving(Clone)]
+pub struct TablePlayer {
+  policy : HashMap<Key, Action>
+}
+
+fn bad_line(line : &str) -> IoError {
+  IoError { kind : InvalidInput, desc : "bad policy table line", detail : Some(line.to_owned()) }
+}
+
+impl TablePlayer {
+  pub fn len(&self) -> uint {
+    self.policy.len()
+  }
+
+  //A width and height line, then one line per position: its tiles in the
+  //order of Board::vec followed by the move as a wasd letter
+  pub fn save(&self, path : &Path) -> IoResult<()> {
+    let mut file = try!(File::create(path));
+    let (width, height) = match self.policy.keys().next() {
+      Some(&(_, width, height)) => (width, height),
+      None => (0, 0)
+    };
+    try!(writeln!(file, "{} {}", width, height));
+    for (&(packed, width, height), &action) in self.policy.iter() {
+      let board = Board::unpack(packed, width, height);
+      let tiles : Vec<String> = board.vec.iter().map(|t| t.to_str()).collect();
+      try!(writeln!(file, "{} {}", tiles.connect(" "), letter(action)));
+    }
+    Ok(())
+  }
+
+  pub fn load(path : &Path) -> IoResult<TablePlayer> {
+    let mut reader = BufferedReader::new(try!(File::open(path)));
+    let header = try!(reader.read_line());
+    let size : Vec<int> = header.as_slice().words().filter_map(|w| from_str(w)).collect();
+    if size.len() != 2 {
+      return Err(bad_line(header.as_slice()))
+    }
+    let (width, height) = (*size.get(0), *size.get(1));
+    let cells = (width * height) as uint;
+    let mut policy = HashMap::new();
+    for line in reader.lines() {
+      let line = try!(line);
+      let words : Vec<&str> = line.as_slice().words().collect();
+      if words.len() == 0 {
+        continue
+      }
+      if words.len() != cells + 1 {
+        return Err(bad_line(line.as_slice()))
+      }
+      let mut board = Board::empty_sized(width, height);
+      for (i, word) in words.iter().take(cells).enumerate() {
+        match from_str::<int>(*word) {
+          Some(tile) => *board.vec.get_mut(i) = tile,
+          None => return Err(bad_line(line.as_slice()))
+        }
+      }
+      match from_letter(*words.get(cells)) {
+        Some(action) => match key(&board) {
+          Some(key) => { policy.insert(key, action); }
+          None => return Err(bad_line(line.as_slice()))
+        },
+        None => return Err(bad_line(line.as_slice()))
+      }
+    }
+    Ok(TablePlayer { policy : policy })
+  }
+}
+
+impl AIPlayer for TablePlayer {
+  fn next_action(&mut self, board : &Board) -> Action {
+    match key(board).and_then(|key| self.policy.find(&key).map(|&action| action)) {
+      Some(action) => action,
+      None => *board.get_actions().get(0)
+    }
+  }
+}
+
+#[test]
+pub fn test_Solver_small_board() {
+  use std::io::fs;
+  use game::Left;
+  let mut solver = Solver::new(ReachTile(16));
+  let mut board = Board::empty_sized(2, 2);
+  board.vec = vec!(8, 8,
+                   2, 4);
+  assert_eq!(solver.value(&board), 1.);
+  assert_eq!(solver.table().next_action(&board), Left);
+  board.vec = vec!(2, 4,
+                   4, 2);
+  assert_eq!(solver.value(&board), 0.);
+
+  //a 2x2 board fills up long before it could make 2048
+  let mut solver = Solver::new(ReachTile(2048));
+  assert_eq!(solver.solve(2, 2), 0.);
+  let mut solver = Solver::new(ReachTile(16));
+  let harder = solver.solve(2, 2);
+  let mut solver = Solver::new(ReachTile(8));
+  let easier = solver.solve(2, 2);
+  assert!(easier > 0.);
+  assert!(easier >= harder);
+
+  let table = solver.table();
+  let path = Path::new("test_policy.txt");
+  table.save(&path).unwrap();
+  let loaded = TablePlayer::load(&path).unwrap();
+  fs::unlink(&path).unwrap();
+  assert_eq!(loaded.len(), table.len());
+}
@@ -0,0 +1,234 @@
+use std::io::{BufferedReader, File, IoResult, IoError, InvalidInput};
+use std::num::Float;
+use rand::{XorShiftRng, SeedableRng};
+
+use game::Board;
+use ai::Evaluator;
+use minimax::{Minimax, spawns};
+
+//Decides where the next tile appears after each move
+pub trait SpawnPolicy {
+  fn spawn(&mut self, board : &Board) -> Board;
+
+  //Board for the start of a game, two spawns on an empty board
+  fn start(&mut self) -> Board {
+    self.start_sized(4, 4)
+  }
+
+  //Same as start on a board of another size
+  fn start_sized(&mut self, width : int, height : int) -> Board {
+    let first = self.spawn(&Board::empty_sized(width, height));
+    self.spawn(&first)
+  }
+
+  //Called on a fresh copy of the policy before game number game of a batch,
+  //so seeded policies give each game the same spawns whatever task plays it
+  fn reseed(&mut self, _game : uint) {
+  }
+}
+
+//The normal game, a 2 or 4 at a uniformly random empty space
+#[deriving(Clone, Show)]
+pub struct RandomSpawn;
+impl SpawnPolicy for RandomSpawn {
+  fn spawn(&mut self, board : &Board) -> Board {
+    board.add_random()
+  }
+}
+
+//Random spawns from a fixed seed, so a set of games can be replayed exactly
+#[deriving(Clone)]
+pub struct SeededSpawn {
+  seed : u32,
+  rng : XorShiftRng
+}
+
+impl SeededSpawn {
+  pub fn new(seed : u32) -> SeededSpawn {
+    SeededSpawn::for_game(seed, 0)
+  }
+
+  //Spawns of one game in a batch, game 0 is the same as new
+  pub fn for_game(seed : u32, game : u32) -> SeededSpawn {
+    //xorshift must not be seeded with all zeros
+    SeededSpawn { seed : seed, rng : SeedableRng::from_seed([seed, 0x2048, 0x193a6754, 0x88d0d4ed ^ game]) }
+  }
+}
+
+impl SpawnPolicy for SeededSpawn {
+  fn spawn(&mut self, board : &Board) -> Board {
+    board.add_random_with(&mut self.rng)
+  }
+
+  fn reseed(&mut self, game : uint) {
+    *self = SeededSpawn::for_game(self.seed, game as u32);
+  }
+}
+
+//Spawn that minimax with depth plies of lookahead values lowest or highest
+fn search_spawn<E : Evaluator>(minimax : &Minimax<E>, board : &Board, worst : bool) -> Board {
+  let inf : f32 = Float::infinity();
+  let mut best = None;
+  let mut best_value = 0.;
+  for next in spawns(board).move_iter() {
+    let value = minimax.max_layer(&next, 0, -inf, inf);
+    let better = if worst { value < best_value } else { value > best_value };
+    if best.is_none() || better {
+      best_value = value;
+      best = Some(next);
+    }
+  }
+  best.unwrap()
+}
+
+//Places the tile that hurts the player most
+#[deriving(Clone)]
+pub struct AdversarialSpawn<E> {
+  minimax : Minimax<E>
+}
+
+impl<E : Evaluator> AdversarialSpawn<E> {
+  pub fn new(depth : uint, evaluator : E) -> AdversarialSpawn<E> {
+    AdversarialSpawn { minimax : Minimax::new(depth, evaluator) }
+  }
+}
+
+impl<E : Evaluator> SpawnPolicy for AdversarialSpawn<E> {
+  fn spawn(&mut self, board : &Board) -> Board {
+    search_spawn(&self.minimax, board, true)
+  }
+}
+
+//Places the tile that helps the player most, for an easy mode
+#[deriving(Clone)]
+pub struct HelpfulSpawn<E> {
+  minimax : Minimax<E>
+}
+
+impl<E : Evaluator> HelpfulSpawn<E> {
+  pub fn new(depth : uint, evaluator : E) -> HelpfulSpawn<E> {
+    HelpfulSpawn { minimax : Minimax::new(depth, evaluator) }
+  }
+}
+
+impl<E : Evaluator> SpawnPolicy for HelpfulSpawn<E> {
+  fn spawn(&mut self, board : &Board) -> Board {
+    search_spawn(&self.minimax, board, false)
+  }
+}
+
+//Replays a fixed list of spawns, then spawns randomly once it runs out. A
+//spawn on a space that is off the board or taken fails naming its line.
+#[deriving(Clone, Show)]
+pub struct ScriptedSpawn {
+  script : Vec<(uint, int)>,
+  //Line of the script each spawn came from, for errors
+  lines : Vec<uint>,
+  next : uint
+}
+
+impl ScriptedSpawn {
+  pub fn new(script : Vec<(uint, int)>) -> ScriptedSpawn {
+    let lines = range(1, script.len() + 1).collect();
+    ScriptedSpawn { script : script, lines : lines, next : 0 }
+  }
+
+  //One spawn per line, the index into Board::vec then the tile: "5 2".
+  //Blank lines and lines starting with # are skipped.
+  pub fn load(path : &Path) -> IoResult<ScriptedSpawn> {
+    let mut reader = BufferedReader::new(try!(File::open(path)));
+    let mut script = vec!();
+    let mut lines = vec!();
+    for (number, line) in reader.lines().enumerate() {
+      let line = try!(line);
+      let words : Vec<&str> = line.as_slice().words().collect();
+      if words.len() == 0 || words.get(0).starts_with("#") {
+        continue
+      }
+      let spawn = if words.len() == 2 {
+        (from_str::<uint>(*words.get(0)), from_str::<int>(*words.get(1)))
+      } else {
+        (None, None)
+      };
+      match spawn {
+        (Some(indx), Some(value)) if value == 2 || value == 4 => {
+          script.push((indx, value));
+          lines.push(number + 1);
+        }
+        _ => return Err(IoError { kind : InvalidInput, desc : "bad spawn line, expected <space> <2 or 4>",
+                                  detail : Some(format!("line {}: {}", number + 1, line.as_slice().trim())) })
+      }
+    }
+    Ok(ScriptedSpawn { script : script, lines : lines, next : 0 })
+  }
+}
+
+impl SpawnPolicy for ScriptedSpawn {
+  fn spawn(&mut self, board : &Board) -> Board {
+    if self.next < self.script.len() {
+      let (indx, value) = *self.script.get(self.next);
+      let line = *self.lines.get(self.next);
+      self.next += 1;
+      if indx >= board.vec.len() || *board.vec.get(indx) != 0 {
+        fail!("spawn script line {}: space {} is not free", line, indx)
+      }
+      return board.add_space((indx, value, 0.))
+    }
+    board.add_random()
+  }
+}
+
+#[test]
+pub fn test_SeededSpawn_repeats() {
+  let a = SeededSpawn::new(7).start();
+  let b = SeededSpawn::new(7).start();
+  assert_eq!(a.vec, b.vec);
+}
+
+#[test]
+pub fn test_ScriptedSpawn() {
+  let mut s = ScriptedSpawn::new(vec!((0, 2), (5, 4)));
+  let board = s.start();
+  assert_eq!(*board.vec.get(0), 2);
+  assert_eq!(*board.vec.get(5), 4);
+  assert_eq!(board.count_empty(), 14);
+  //past the end of the script spawns are random
+  let board = s.spawn(&board);
+  assert_eq!(board.count_empty(), 13);
+}
+
+#[test]
+#[should_fail]
+pub fn test_ScriptedSpawn_taken_space() {
+  let mut s = ScriptedSpawn::new(vec!((0, 2), (5, 4), (0, 4)));
+  let board = s.start();
+  s.spawn(&board);
+}
+
+#[test]
+pub fn test_ScriptedSpawn_load_rejects_bad_tiles() {
+  use std::io::fs;
+  let path = Path::new("test_script.txt");
+  File::create(&path).write_str("# first two spawns\n0 2\n\n5 3\n").unwrap();
+  let loaded = ScriptedSpawn::load(&path);
+  fs::unlink(&path).unwrap();
+  match loaded {
+    Err(e) => assert_eq!(e.detail, Some("line 4: 5 3".to_owned())),
+    Ok(_) => fail!("a 3 should not load")
+  }
+}
+
+#[test]
+pub fn test_AdversarialSpawn_no_worse_than_helpful() {
+  use ai::Heuristic;
+  let mut board = Board::empty();
+  board.vec  = vec!(0, 2, 4, 2,
+                    0, 2, 0, 2,
+                    0, 4, 4, 2,
+                    0, 4, 2, 2);
+  let evil = AdversarialSpawn::new(1, Heuristic::default()).spawn(&board);
+  let nice = HelpfulSpawn::new(1, Heuristic::default()).spawn(&board);
+  let minimax = Minimax::new(1, Heuristic::default());
+  let inf : f32 = Float::infinity();
+  assert!(minimax.max_layer(&evil, 0, -inf, inf) <= minimax.max_layer(&nice, 0, -inf, inf));
+}
@@ -0,0 +1,201 @@
+use std::num::Float;
+use std::cmp::{Less, Greater, Equal};
+
+use ai::Report;
+
+//Standard normal quantile for 95% intervals
+pub static Z_95 : f64 = 1.96;
+
+//Wilson score interval on a rate of successes out of n. Unlike the normal
+//approximation it stays inside 0..1 and behaves near 0 and 1.
+pub fn wilson(successes : uint, n : uint, z : f64) -> (f64, f64) {
+  if n == 0 {
+    return (0., 1.)
+  }
+  let n = n as f64;
+  let p = successes as f64 / n;
+  let z2 = z * z;
+  let denom = 1. + z2 / n;
+  let center = (p + z2 / (2. * n)) / denom;
+  let half = z * (p * (1. - p) / n + z2 / (4. * n * n)).sqrt() / denom;
+  ((center - half).max(0.), (center + half).min(1.))
+}
+
+//Summary of a sample of numbers
+#[deriving(Clone, Show)]
+pub struct Distribution {
+  pub mean : f64,
+  pub std_dev : f64, //Sample standard deviation, 0 with fewer than 2 values
+  sorted : Vec<f64>
+}
+
+impl Distribution {
+  pub fn new(values : &Vec<f64>) -> Distribution {
+    let mut sorted = values.clone();
+    sorted.sort_by(|a, b| if a < b { Less } else if a > b { Greater } else { Equal });
+    let n = sorted.len() as f64;
+    let mean = if sorted.len() == 0 { 0. } else { sorted.iter().fold(0., |t, &v| t + v) / n };
+    let std_dev = if sorted.len() < 2 {
+      0.
+    } else {
+      (sorted.iter().fold(0., |t, &v| t + (v - mean) * (v - mean)) / (n - 1.)).sqrt()
+    };
+    Distribution { mean : mean, std_dev : std_dev, sorted : sorted }
+  }
+
+  pub fn len(&self) -> uint {
+    self.sorted.len()
+  }
+
+  //p from 0 to 100, interpolating between the closest ranks
+  pub fn percentile(&self, p : f64) -> f64 {
+    if self.sorted.len() == 0 {
+      return 0.
+    }
+    let rank = p.max(0.).min(100.) / 100. * (self.sorted.len() - 1) as f64;
+    let lo = rank.floor() as uint;
+    let hi = rank.ceil() as uint;
+    let frac = rank - lo as f64;
+    *self.sorted.get(lo) * (1. - frac) + *self.sorted.get(hi) * frac
+  }
+
+  pub fn min(&self) -> f64 {
+    self.percentile(0.)
+  }
+
+  pub fn median(&self) -> f64 {
+    self.percentile(50.)
+  }
+
+  pub fn max(&self) -> f64 {
+    self.percentile(100.)
+  }
+
+  //Standard error of the mean
+  pub fn std_err(&self) -> f64 {
+    if self.sorted.len() == 0 { 0. } else { self.std_dev / (self.sorted.len() as f64).sqrt() }
+  }
+
+  pub fn print(&self, name : &str) {
+    println!("{}: mean {:.1} +- {:.1} sd {:.1} | min {} p10 {} p25 {} median {} p75 {} p90 {} max {}",
+             name, self.mean, Z_95 * self.std_err(), self.std_dev, self.min(), self.percentile(10.),
+             self.percentile(25.), self.median(), self.percentile(75.), self.percentile(90.), self.max());
+  }
+}
+
+//How many games reached a tile, with a 95% interval on the rate
+#[deriving(Clone, Show)]
+pub struct ReachRate {
+  pub tile : int,
+  pub reached : uint,
+  pub games : uint
+}
+
+impl ReachRate {
+  pub fn rate(&self) -> f64 {
+    if self.games == 0 { 0. } else { self.reached as f64 / self.games as f64 }
+  }
+
+  pub fn interval(&self) -> (f64, f64) {
+    wilson(self.reached, self.games, Z_95)
+  }
+}
+
+//Count of games that ended with each best tile, smallest tile first
+pub fn tile_histogram(tiles : &Vec<int>) -> Vec<(int, uint)> {
+  let mut sorted = tiles.clone();
+  sorted.sort();
+  let mut histogram : Vec<(int, uint)> = vec!();
+  for &tile in sorted.iter() {
+    let same = match histogram.last() {
+      Some(&(last, _)) => last == tile,
+      None => false
+    };
+    if same {
+      let last = histogram.len() - 1;
+      *histogram.get_mut(last).mut1() += 1;
+    } else {
+      histogram.push((tile, 1));
+    }
+  }
+  histogram
+}
+
+fn column(reports : &Vec<Report>, f : |&Report| -> f64) -> Distribution {
+  let mut values = vec!();
+  for report in reports.iter() {
+    values.push(f(report));
+  }
+  Distribution::new(&values)
+}
+
+//Everything print_reports shows about a batch of games
+pub struct BatchStats {
+  pub games : uint,
+  pub score : Distribution,
+  pub best_tile : Distribution,
+  pub moves : Distribution,
+  pub seconds : Distribution, //Wall time per game
+  pub reach : Vec<ReachRate>,
+  pub tiles : Vec<(int, uint)>
+}
+
+impl BatchStats {
+  pub fn new(reports : &Vec<Report>, thresholds : &Vec<int>) -> BatchStats {
+    let tiles : Vec<int> = reports.iter().map(|r| r.summary.best_tile).collect();
+    BatchStats {
+      games : reports.len(),
+      score : column(reports, |r| r.score as f64),
+      best_tile : column(reports, |r| r.summary.best_tile as f64),
+      moves : column(reports, |r| r.moves as f64),
+      seconds : column(reports, |r| r.time_ns as f64 / 1e9),
+      reach : thresholds.iter().map(|&tile| {
+        ReachRate { tile : tile, reached : tiles.iter().filter(|&&t| t >= tile).count(), games : tiles.len() }
+      }).collect(),
+      tiles : tile_histogram(&tiles)
+    }
+  }
+
+  pub fn print(&self) {
+    println!("Scores from {} Samples\n==========", self.games);
+    for reach in self.reach.iter() {
+      let (low, high) = reach.interval();
+      println!("{}: {:.3} (95% {:.3} - {:.3})", reach.tile, reach.rate(), low, high);
+    }
+    if self.games == 0 {
+      return
+    }
+    self.score.print("Score");
+    self.best_tile.print("Best tile");
+    self.moves.print("Moves");
+    println!("Seconds per game: mean {:.2} median {:.2} max {:.2}", self.seconds.mean,
+             self.seconds.median(), self.seconds.max());
+    let histogram : Vec<String> = self.tiles.iter().map(|&(tile, count)| format!("{} x{}", tile, count)).collect();
+    println!("Best tiles: {}", histogram.as_slice().connect(", "));
+  }
+}
+
+#[test]
+pub fn test_wilson() {
+  //60 out of 100 is roughly 0.50 to 0.69, so 0.63 is not a real improvement
+  let (low, high) = wilson(60, 100, Z_95);
+  assert!((low - 0.502).abs() < 1e-3, "{}", low);
+  assert!((high - 0.691).abs() < 1e-3, "{}", high);
+  assert!(low < 0.63 && 0.63 < high);
+  let (low, high) = wilson(0, 10, Z_95);
+  assert!(low < 1e-9);
+  assert!(high > 0. && high < 0.35);
+}
+
+#[test]
+pub fn test_Distribution() {
+  let d = Distribution::new(&vec!(4., 1., 3., 2.));
+  assert_eq!(d.mean, 2.5);
+  assert_eq!(d.min(), 1.);
+  assert_eq!(d.max(), 4.);
+  assert_eq!(d.median(), 2.5);
+  assert_eq!(d.percentile(25.), 1.75);
+  assert!((d.std_dev - (5f64 / 3.).sqrt()).abs() < 1e-9);
+  assert_eq!(tile_histogram(&vec!(2048, 1024, 2048, 512)),
+             vec!((512, 1), (1024, 1), (2048, 2)));
+}
@@ -0,0 +1,158 @@
+use std::num::{Float, pow};
+use std::sync::Arc;
+
+use game::{Board, exponent};
+use ai::{Evaluator, Score, Weights, herustic, rank, line_monotonicity, line_merges};
+use ai::{near_game_over, best_tile_not_in_center, snake};
+
+//Rows of 4 tiles packed as 4 bit exponents, the first tile in the top bits
+static ROWS : uint = 65536;
+//Largest tile that fits in 4 bits
+static MAX_TILE : int = 32768;
+
+fn unpack(row : uint) -> [int, ..4] {
+  let mut tiles = [0, ..4];
+  for i in range(0u, 4) {
+    let e = (row >> (12 - 4*i)) & 15;
+    tiles[i] = if e == 0 { 0 } else { pow(2, e) };
+  }
+  tiles
+}
+
+//The terms of herustic that only look at one row or column
+struct RowTerms {
+  empty : f32,
+  squares : int,
+  smooth : f32,
+  monotonicity : f32,
+  merges : f32
+}
+
+//RowTerms of every packed row, indexed by the row
+pub struct RowTables {
+  rows : Vec<RowTerms>
+}
+
+impl RowTables {
+  pub fn new() -> RowTables {
+    let mut rows = Vec::with_capacity(ROWS);
+    for row in range(0, ROWS) {
+      let line = unpack(row);
+      let mut smooth = 0.;
+      for i in range(0u, 3) {
+        smooth -= (rank(line[i]) - rank(line[i+1])).abs();
+      }
+      rows.push(RowTerms {
+        empty : line.iter().filter(|&&t| t == 0).count() as f32,
+        squares : line.iter().map(|&t| t*t).fold(0, |a, b| a + b),
+        smooth : smooth,
+        monotonicity : line_monotonicity(line.as_slice()),
+        merges : line_merges(line.as_slice())
+      });
+    }
+    RowTables { rows : rows }
+  }
+
+  fn get<'a>(&'a self, row : uint) -> &'a RowTerms {
+    self.rows.get(row)
+  }
+}
+
+//Same as herustic, but the row and column terms are looked up instead of
+//worked out. Boards that are not 4x4 or have a tile too large to pack fall
+//back to herustic.
+pub fn table_herustic(board : &Board, weights : &Weights, tables : &RowTables) -> Score {
+  if board.width != 4 || board.height != 4 {
+    return herustic(board, weights)
+  }
+  let mut e = [0u, ..16];
+  let mut ranks = [0f32, ..16];
+  let mut best = 0;
+  for (i, &tile) in board.vec.iter().enumerate() {
+    if tile > MAX_TILE {
+      return herustic(board, weights)
+    }
+    if tile > best {
+      best = tile;
+    }
+    e[i] = exponent(tile);
+    ranks[i] = e[i] as f32;
+  }
+
+  let mut empty_count = 0.;
+  let mut squared_sum = 0;
+  let mut smooth = 0.;
+  let mut monotonicity = 0.;
+  let mut merges = 0.;
+  for i in range(0u, 4) {
+    let row = tables.get((e[4*i] << 12) | (e[4*i + 1] << 8) | (e[4*i + 2] << 4) | e[4*i + 3]);
+    let col = tables.get((e[i] << 12) | (e[i + 4] << 8) | (e[i + 8] << 4) | e[i + 12]);
+    empty_count += row.empty;
+    squared_sum += row.squares;
+    smooth += row.smooth + col.smooth;
+    monotonicity += row.monotonicity + col.monotonicity;
+    merges += row.merges + col.merges;
+  }
+
+  Score::weighted(&[
+    empty_count,
+    near_game_over(empty_count),
+    (squared_sum as f32).log2(),
+    best_tile_not_in_center(board, best),
+    -(smooth * smooth) / 200.0,
+    monotonicity,
+    snake(ranks.as_slice(), 4, 4),
+    merges], weights)
+}
+
+//herustic through RowTables, the tables are shared between clones
+#[deriving(Clone)]
+pub struct TableHeuristic {
+  weights : Weights,
+  tables : Arc<RowTables>
+}
+
+impl TableHeuristic {
+  pub fn new(weights : Weights) -> TableHeuristic {
+    TableHeuristic { weights : weights, tables : Arc::new(RowTables::new()) }
+  }
+}
+
+impl Evaluator for TableHeuristic {
+  fn evaluate(&self, board : &Board) -> f32 {
+    table_herustic(board, &self.weights, &*self.tables).as_f32()
+  }
+
+  fn breakdown(&self, board : &Board) -> Option<Score> {
+    Some(table_herustic(board, &self.weights, &*self.tables))
+  }
+}
+
+#[test]
+pub fn test_table_herustic_matches() {
+  use ai::{AIPlayer, RandomPlayer};
+  let weights = Weights::default();
+  let tables = RowTables::new();
+  let check = |board : &Board| {
+    let slow = herustic(board, &weights).as_f32();
+    let fast = table_herustic(board, &weights, &tables).as_f32();
+    assert!((slow - fast).abs() <= 1e-3 * (1. + slow.abs()), "{} {} {}", board, slow, fast);
+  };
+  let mut player = RandomPlayer::new();
+  for _ in range(0u, 5) {
+    let mut board = Board::new();
+    while board.get_actions().len() > 0 {
+      check(&board);
+      board = board.move(player.next_action(&board)).add_random();
+    }
+    check(&board);
+  }
+  let mut board = Board::empty();
+  board.vec = vec!(32768, 16384, 8, 2,
+                   4, 0, 0, 2,
+                   0, 2, 0, 0,
+                   0, 0, 0, 65536);
+  check(&board);
+  *board.vec.get_mut(15) = 0;
+  check(&board);
+}
@@ -0,0 +1,77 @@
+extern crate rand;
+
+use std::os;
+use std::cmp::min;
+use std::from_str::FromStr;
+use ntuple::{NTupleNetwork, default_tuples};
+
+pub mod game;
+pub mod ai;
+pub mod ntuple;
+pub mod minimax;
+pub mod spawn;
+pub mod record;
+pub mod pool;
+pub mod stats;
+
+static REPORT_EVERY : uint = 1000;
+static CHECKPOINT_EVERY : uint = 10000;
+//Past this many halvings the rate is as good as 0
+static MAX_HALVINGS : uint = 30;
+
+fn arg<T : FromStr>(args : &Vec<String>, i : uint, default : T) -> T {
+  if args.len() > i {
+    from_str(args.get(i).as_slice()).expect("could not parse argument")
+  } else {
+    default
+  }
+}
+
+//Train an n-tuple network with TD learning over self played games.
+//Resumes from the weight file if it already exists and checkpoints to it.
+//The learning rate is halved every decay_every games.
+pub fn main() {
+  let args = os::args();
+  if args.len() < 2 {
+    println!("usage: train <weights> [games] [alpha] [lambda] [decay_every]");
+    return
+  }
+  let path = Path::new(args.get(1).as_slice());
+  let games : uint = arg(&args, 2, 1000000);
+  let alpha : f32 = arg(&args, 3, 0.1);
+  let lambda : f32 = arg(&args, 4, 0.);
+  let decay_every : uint = arg(&args, 5, 250000);
+  if decay_every == 0 {
+    println!("decay_every must be at least 1");
+    return
+  }
+
+  let mut network = if path.exists() {
+    println!("resuming from {}", path.display());
+    NTupleNetwork::load(&path).unwrap()
+  } else {
+    NTupleNetwork::new(default_tuples())
+  };
+
+  let mut total_score = 0;
+  let mut p_2048 = 0;
+  for game in range(1, games + 1) {
+    let halvings = min((game - 1) / decay_every, MAX_HALVINGS);
+    let rate = alpha / (1u << halvings) as f32;
+    let (board, score) = network.learn_game(rate, lambda);
+    total_score += score;
+    if board.get_best_tile() >= 2048 {
+      p_2048 += 1;
+    }
+    if game % REPORT_EVERY == 0 {
+      println!("{} games, alpha {}: mean score {}, 2048: {}", game, rate,
+               total_score as f32 / REPORT_EVERY as f32,
+               p_2048 as f32 / REPORT_EVERY as f32);
+      total_score = 0;
+      p_2048 = 0;
+    }
+    if game % CHECKPOINT_EVERY == 0 || game == games {
+      network.save(&path).unwrap();
+    }
+  }
+}
@@ -0,0 +1,151 @@
+extern crate rand;
+
+use std::os;
+use std::io::{BufferedReader, File, IoResult};
+use std::from_str::FromStr;
+use std::cmp::{Less, Greater, Equal};
+use rand::{Rng, task_rng};
+use rand::distributions::{Normal, IndependentSample};
+use ai::{AIPlayer, Player, ExpectiMax, Heuristic, Weights};
+use spawn::SeededSpawn;
+
+pub mod game;
+pub mod ai;
+pub mod minimax;
+pub mod spawn;
+pub mod record;
+pub mod pool;
+pub mod stats;
+
+static POPULATION : uint = 16;
+//Best candidates copied unchanged into the next generation
+static ELITE : uint = 4;
+//Standard deviation of the mutation, relative to the size of the weight
+static MUTATION : f64 = 0.2;
+//Search used to play the evaluation games, kept shallow so tuning is quick
+static DEPTH : uint = 2;
+static NUM_EXPECTI : uint = 4;
+
+fn arg<T : FromStr>(args : &Vec<String>, i : uint, default : T) -> T {
+  if args.len() > i {
+    from_str(args.get(i).as_slice()).expect("could not parse argument")
+  } else {
+    default
+  }
+}
+
+//Mean score over the games played with seeds 0 to games. Every candidate
+//sees the same spawns and search samples so they are compared fairly.
+fn fitness(weights : &Weights, games : uint) -> f32 {
+  let mut total = 0;
+  for seed in range(0, games) {
+    let mut player = ExpectiMax::new(DEPTH, NUM_EXPECTI, Heuristic::new(weights.clone()));
+    player.reseed(seed as u32, 0);
+    let (report, _) = Player::run_game(&mut player, &mut SeededSpawn::new(seed as u32), false);
+    total += report.score;
+  }
+  total as f32 / games as f32
+}
+
+fn evaluate(population : &Vec<Weights>, games : uint) -> Vec<(f32, Weights)> {
+  let (tx, rx) : (Sender<(uint, f32)>, Receiver<(uint, f32)>) = channel();
+  for (idx, weights) in population.iter().enumerate() {
+    let weights = weights.clone();
+    let tx = tx.clone();
+    spawn(proc() {
+      tx.send((idx, fitness(&weights, games)));
+    });
+  }
+  let mut scores = Vec::from_elem(population.len(), 0f32);
+  for _ in range(0, population.len()) {
+    let (idx, score) = rx.recv();
+    *scores.get_mut(idx) = score;
+  }
+  let mut scored : Vec<(f32, Weights)> = scores.move_iter().zip(population.iter().map(|w| w.clone())).collect();
+  scored.sort_by(|&(a, _), &(b, _)| if a > b { Less } else if a < b { Greater } else { Equal });
+  scored
+}
+
+//Better of two random members
+fn tournament<'a>(scored : &'a Vec<(f32, Weights)>) -> &'a Weights {
+  let mut rng = task_rng();
+  let a = rng.gen_range(0, scored.len());
+  let b = rng.gen_range(0, scored.len());
+  //scored is sorted best first
+  let (_, ref weights) = *scored.get(if a < b { a } else { b });
+  weights
+}
+
+fn breed(scored : &Vec<(f32, Weights)>) -> Vec<Weights> {
+  let mut rng = task_rng();
+  let noise = Normal::new(0., MUTATION);
+  let mut next : Vec<Weights> = scored.iter().take(ELITE).map(|&(_, ref w)| w.clone()).collect();
+  while next.len() < POPULATION {
+    let a = tournament(scored).as_vec();
+    let b = tournament(scored).as_vec();
+    let child = a.iter().zip(b.iter()).map(|(&x, &y)| {
+      let w = if rng.gen() { x } else { y };
+      w + (noise.ind_sample(&mut rng) as f32) * (w.abs() + 0.01)
+    }).collect();
+    next.push(Weights::from_vec(&child));
+  }
+  next
+}
+
+//One line per candidate, its weights in the order of Weights::as_vec
+fn save_population(path : &Path, generation : uint, population : &Vec<Weights>) -> IoResult<()> {
+  let mut file = try!(File::create(path));
+  try!(writeln!(file, "{}", generation));
+  for weights in population.iter() {
+    let words : Vec<String> = weights.as_vec().iter().map(|w| w.to_str()).collect();
+    try!(writeln!(file, "{}", words.connect(" ")));
+  }
+  Ok(())
+}
+
+fn load_population(path : &Path) -> IoResult<(uint, Vec<Weights>)> {
+  let mut reader = BufferedReader::new(try!(File::open(path)));
+  let generation = from_str(try!(reader.read_line()).as_slice().trim()).unwrap_or(0);
+  let mut population = vec!();
+  for line in reader.lines() {
+    let line = try!(line);
+    let v : Vec<f32> = line.as_slice().words().filter_map(|w| from_str(w)).collect();
+    if v.len() == 8 {
+      population.push(Weights::from_vec(&v));
+    }
+  }
+  Ok((generation, population))
+}
+
+//Tune the weights of herustic with a genetic algorithm.
+//The population is checkpointed after every generation and resumed from if
+//it exists, the best weights found so far are written to the output file.
+pub fn main() {
+  let args = os::args();
+  if args.len() < 2 {
+    println!("usage: tune <weights out> [generations] [games per candidate]");
+    return
+  }
+  let out = Path::new(args.get(1).as_slice());
+  let generations : uint = arg(&args, 2, 50);
+  let games : uint = arg(&args, 3, 20);
+  let checkpoint = out.with_extension("population");
+
+  let (start, mut population) = match load_population(&checkpoint) {
+    Ok((generation, population)) if population.len() > 0 => {
+      println!("resuming from generation {}", generation);
+      (generation, population)
+    }
+    //the defaults and mutations of them
+    _ => (0, breed(&vec!((0., Weights::default()))))
+  };
+
+  for generation in range(start, generations) {
+    let scored = evaluate(&population, games);
+    let (best_score, ref best) = *scored.get(0);
+    println!("generation {}: best {} mean score {}", generation, best, best_score);
+    best.save(&out).unwrap();
+    population = breed(&scored);
+    save_population(&checkpoint, generation + 1, &population).unwrap();
+  }
+}
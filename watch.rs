@@ -1,11 +1,47 @@
 extern crate rand;
 
-use ai::{Player, ExpectiMax};
+use std::os;
+use ai::{ExpectiMax, Heuristic, Weights, Direction, Space};
+use spawn::{SpawnPolicy, RandomSpawn};
 
 pub mod game;
 pub mod ai;
+pub mod minimax;
+pub mod spawn;
+pub mod record;
+pub mod pool;
+pub mod stats;
 
+//Watch the AI play, showing the value of every move it considered
+//usage: watch [herustic weights file]
 pub fn main() {
-  let expecti = ExpectiMax::new(7, 16);
-  Player::play_interactive(expecti);
+  let args = os::args();
+  let heuristic = if args.len() > 1 {
+    Heuristic::new(Weights::load(&Path::new(args.get(1).as_slice())).unwrap())
+  } else {
+    Heuristic::default()
+  };
+  let expecti = ExpectiMax::new(7, 16, heuristic);
+  let mut spawner = RandomSpawn;
+  let mut board = spawner.start();
+  let mut moves = 0;
+  println!("{}", board);
+  while board.get_actions().len() > 0 {
+    let analysis = expecti.analyse(&board);
+    for a in analysis.actions.iter() {
+      let pv : Vec<String> = a.pv.iter().map(|m| match *m {
+        Direction(action) => format!("{}", action),
+        Space((indx, value, _)) => format!("{}@{}", value, indx),
+        _ => "".to_owned()
+      }).collect();
+      println!("{:5} {:10.2} {}", a.action, a.value, pv.connect(" "));
+    }
+    let best = analysis.best();
+    println!("{} by {:.2}", best.action, analysis.margin());
+    println!("{}", best.score);
+    board = spawner.spawn(&board.move(best.action));
+    moves += 1;
+    println!("{}", board);
+  }
+  println!("{} moves, {}", moves, board.summary());
 }